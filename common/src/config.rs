@@ -1,6 +1,7 @@
-use crate::default_option::DefaultOption;
+use crate::{default_option::DefaultOption, models::*};
 
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::IpAddr};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Map {
@@ -17,6 +18,121 @@ pub struct Creature {
     pub family: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hero {
+    /// Class suffix after `RxGameContent.RxPawn_`
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+impl Hero {
+    /// Looks up a hero by id, unknown ids are passed through unchanged as the name.
+    pub fn find(heroes: &[Hero], id: &str) -> Hero {
+        heroes.iter().find(|hero| hero.id.eq_ignore_ascii_case(id)).cloned().unwrap_or_else(|| Hero {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            role: None,
+            icon: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    #[serde(flatten)]
+    pub spec: StartSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    Read,
+    Start,
+    Kill,
+    AdminPw,
+    ManageMods,
+    /// Grants every other scope
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    /// The key in plain text, prefer storing only its `hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Salted hash of the key, as printed by `gcp generate-key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiKeyConfig {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|s| *s == scope || *s == ApiScope::Admin)
+    }
+}
+
+/// Credentials required to use a read-only API route.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAccess {
+    Public,
+    /// Any valid API key, or being logged in to the web UI
+    AnyKey,
+    /// An API key with the given scope
+    Scope(ApiScope),
+}
+
+/// Which parts of the web UI require logging in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum WebLogin {
+    Disabled,
+    /// Only starting servers requires logging in
+    Start,
+    /// Every page requires logging in
+    All,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserConfig {
+    pub name: String,
+    /// PBKDF2 hash of the password, as printed by `gcp hash-password`.
+    pub password_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogRule {
+    pub event: String,
+    pub pattern: String,
+    pub description: String,
+    #[serde(default)]
+    pub data: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveRetention {
+    pub max_runs: Option<usize>,
+    pub max_age_days: Option<u64>,
+    pub max_size_mb: Option<u64>,
+}
+
+/// Limits for mutating endpoints and starts, a client is identified by its API key, web UI login or IP address.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimits {
+    pub requests_per_minute_per_ip: Option<usize>,
+    pub requests_per_minute_per_key: Option<usize>,
+    /// Running instances and queued requests of a single client
+    pub max_concurrent_instances: Option<usize>,
+    pub max_starts_per_hour: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(skip_serializing_if = "DefaultOption::is_default")]
@@ -55,9 +171,107 @@ pub struct Config {
     #[serde(default = "default_creatures")]
     pub creatures: DefaultOption<Vec<Creature>>,
 
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_heroes")]
+    pub heroes: DefaultOption<Vec<Hero>>,
+
     #[serde(skip_serializing_if = "DefaultOption::is_default")]
     #[serde(default = "default_api_key")]
     pub api_key: DefaultOption<Option<String>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_api_keys")]
+    pub api_keys: DefaultOption<Vec<ApiKeyConfig>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_access_policy")]
+    pub access_policy: DefaultOption<BTreeMap<String, RouteAccess>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_web_login")]
+    pub web_login: DefaultOption<WebLogin>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_users")]
+    pub users: DefaultOption<Vec<UserConfig>>,
+
+    /// Base64 encoded 256-bit key used to encrypt session cookies.
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_secret_key")]
+    pub secret_key: DefaultOption<Option<String>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_start_queue_size")]
+    pub start_queue_size: DefaultOption<usize>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_presets")]
+    pub presets: DefaultOption<Vec<Preset>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_preset_path")]
+    pub preset_path: DefaultOption<String>,
+
+    /// Keys of `DefaultGame.ini` that start requests may override, any key except `AdminPassword` if not set
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_override_keys")]
+    pub override_keys: DefaultOption<Option<Vec<String>>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_api_key_path")]
+    pub api_key_path: DefaultOption<String>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_schedule_path")]
+    pub schedule_path: DefaultOption<String>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_archive_path")]
+    pub archive_path: DefaultOption<String>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_archive_retention")]
+    pub archive_retention: DefaultOption<ArchiveRetention>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_rate_limits")]
+    pub rate_limits: DefaultOption<RateLimits>,
+
+    /// Reverse proxies whose `X-Real-IP` header is trusted as the client address
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: DefaultOption<Vec<IpAddr>>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_match_history_path")]
+    pub match_history_path: DefaultOption<String>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_audit_log_path")]
+    pub audit_log_path: DefaultOption<String>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_live_log_max_lines")]
+    pub live_log_max_lines: DefaultOption<usize>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_live_log_max_bytes")]
+    pub live_log_max_bytes: DefaultOption<usize>,
+
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_log_poll_interval_ms")]
+    pub log_poll_interval_ms: DefaultOption<u64>,
+
+    /// Server-sent event streams open at the same time, each one keeps an HTTP worker busy
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_max_event_streams")]
+    pub max_event_streams: DefaultOption<usize>,
+
+    /// Rules turning log lines into events, applied after the [`builtin_log_rules`]. Team, leave, guardian and
+    /// victory events are only tracked once a rule emits them.
+    #[serde(skip_serializing_if = "DefaultOption::is_default")]
+    #[serde(default = "default_log_rules")]
+    pub log_rules: DefaultOption<Vec<LogRule>>,
 }
 
 fn default_http_port() -> DefaultOption<u16> {
@@ -213,6 +427,38 @@ fn default_creatures() -> DefaultOption<Vec<Creature>> {
     ]);
 }
 
+fn default_heroes() -> DefaultOption<Vec<Hero>> {
+    return DefaultOption::with_default(vec![
+        Hero { id: "Aisling".to_owned(), name: "Aisling".to_owned(), role: Some("Support".to_owned()), icon: None },
+        Hero { id: "Beckett".to_owned(), name: "Beckett".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Charnok".to_owned(), name: "Charnok".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Griselma".to_owned(), name: "Griselma".to_owned(), role: Some("Support".to_owned()), icon: None },
+        Hero { id: "HK206".to_owned(), name: "HK-206".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Imani".to_owned(), name: "Imani".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Knossos".to_owned(), name: "Knossos".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Margrave".to_owned(), name: "Margrave".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero { id: "Mozu".to_owned(), name: "Mozu".to_owned(), role: Some("Support".to_owned()), icon: None },
+        Hero { id: "Pakko".to_owned(), name: "Pakko".to_owned(), role: Some("Tank".to_owned()), icon: None },
+        Hero { id: "Roland".to_owned(), name: "Roland".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Saros".to_owned(), name: "Saros".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero { id: "Tripp".to_owned(), name: "Tripp".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero {
+            id: "UncleSven".to_owned(),
+            name: "Uncle Sven".to_owned(),
+            role: Some("Support".to_owned()),
+            icon: None,
+        },
+        Hero { id: "Vadasi".to_owned(), name: "Vadasi".to_owned(), role: Some("Support".to_owned()), icon: None },
+        Hero { id: "Voden".to_owned(), name: "Voden".to_owned(), role: Some("Support".to_owned()), icon: None },
+        Hero { id: "Wu".to_owned(), name: "Wu".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero { id: "Xenobia".to_owned(), name: "Xenobia".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Zandora".to_owned(), name: "Zandora".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+        Hero { id: "Tyto".to_owned(), name: "Tyto the Swift".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero { id: "Kajir".to_owned(), name: "Kajir".to_owned(), role: Some("Melee".to_owned()), icon: None },
+        Hero { id: "Ezren".to_owned(), name: "Ezren".to_owned(), role: Some("Ranged".to_owned()), icon: None },
+    ]);
+}
+
 fn default_default_creatures() -> DefaultOption<Vec<String>> {
     return DefaultOption::with_default(vec!["bloomer".to_owned(), "cerb".to_owned(), "cyclops".to_owned()]);
 }
@@ -221,6 +467,126 @@ fn default_api_key() -> DefaultOption<Option<String>> {
     return DefaultOption::with_default(None);
 }
 
+fn default_api_keys() -> DefaultOption<Vec<ApiKeyConfig>> {
+    return DefaultOption::with_default(Vec::new());
+}
+
+fn default_access_policy() -> DefaultOption<BTreeMap<String, RouteAccess>> {
+    return DefaultOption::with_default(BTreeMap::new());
+}
+
+fn default_web_login() -> DefaultOption<WebLogin> {
+    return DefaultOption::with_default(WebLogin::Disabled);
+}
+
+fn default_users() -> DefaultOption<Vec<UserConfig>> {
+    return DefaultOption::with_default(Vec::new());
+}
+
+fn default_secret_key() -> DefaultOption<Option<String>> {
+    return DefaultOption::with_default(None);
+}
+
+fn default_start_queue_size() -> DefaultOption<usize> {
+    return DefaultOption::with_default(0);
+}
+
+fn default_presets() -> DefaultOption<Vec<Preset>> {
+    return DefaultOption::with_default(Vec::new());
+}
+
+fn default_preset_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("presets".to_owned());
+}
+
+fn default_override_keys() -> DefaultOption<Option<Vec<String>>> {
+    return DefaultOption::with_default(None);
+}
+
+fn default_api_key_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("api_keys".to_owned());
+}
+
+fn default_schedule_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("schedules".to_owned());
+}
+
+fn default_archive_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("archive".to_owned());
+}
+
+fn default_archive_retention() -> DefaultOption<ArchiveRetention> {
+    return DefaultOption::with_default(ArchiveRetention { max_runs: Some(100), max_age_days: None, max_size_mb: None });
+}
+
+fn default_rate_limits() -> DefaultOption<RateLimits> {
+    return DefaultOption::with_default(RateLimits {
+        requests_per_minute_per_ip: Some(60),
+        requests_per_minute_per_key: None,
+        max_concurrent_instances: None,
+        max_starts_per_hour: None,
+    });
+}
+
+fn default_trusted_proxies() -> DefaultOption<Vec<IpAddr>> {
+    return DefaultOption::with_default(Vec::new());
+}
+
+fn default_match_history_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("matches".to_owned());
+}
+
+fn default_audit_log_path() -> DefaultOption<String> {
+    return DefaultOption::with_default("audit".to_owned());
+}
+
+fn default_live_log_max_lines() -> DefaultOption<usize> {
+    return DefaultOption::with_default(10_000);
+}
+
+fn default_live_log_max_bytes() -> DefaultOption<usize> {
+    return DefaultOption::with_default(4 * 1024 * 1024);
+}
+
+fn default_log_poll_interval_ms() -> DefaultOption<u64> {
+    return DefaultOption::with_default(250);
+}
+
+fn default_max_event_streams() -> DefaultOption<usize> {
+    return DefaultOption::with_default(16);
+}
+
+fn default_log_rules() -> DefaultOption<Vec<LogRule>> {
+    return DefaultOption::with_default(Vec::new());
+}
+
+/// Rules for the events the server relies on, always applied before configured and mod rules.
+pub fn builtin_log_rules() -> Vec<LogRule> {
+    return vec![
+        LogRule {
+            event: EVENT_PLAYER_JOIN.to_owned(),
+            pattern: r#"DevNet: Join succeeded: (?P<name>.*?) playerid="#.to_owned(),
+            description: "Player ${name} joined".to_owned(),
+            data: crate::btree_map! { "name".to_owned() => "${name}".to_owned() },
+        },
+        LogRule {
+            event: EVENT_PLAYER_LOCK.to_owned(),
+            pattern: r#"RxPlayerController::PlayerWaiting:FinishLockingCharacterSelection (?P<name>.*?) LOCKED HeroProviderIndex:'\d+' RxGameContent\.RxPawn_(?P<hero>.*?)$"#.to_owned(),
+            description: "Player ${name} locked char ${hero_name}".to_owned(),
+            data: crate::btree_map! {
+                "name".to_owned() => "${name}".to_owned(),
+                "hero".to_owned() => "${hero}".to_owned()
+            },
+        },
+        LogRule {
+            event: EVENT_MATCH_STARTING.to_owned(),
+            pattern: r#"Starting match..."#.to_owned(),
+            description: "Match starting".to_owned(),
+            data: BTreeMap::new(),
+        },
+    ];
+}
+
 impl Default for Config {
     fn default() -> Self {
         return serde_json::from_str("{}").unwrap();
@@ -228,6 +594,48 @@ impl Default for Config {
 }
 
 impl Config {
+    /// All API keys from the config, the legacy `api_key` is treated as a key with the admin scope.
+    pub fn configured_api_keys(&self) -> Vec<ApiKeyConfig> {
+        let legacy = self.api_key.as_ref().map(|key| ApiKeyConfig {
+            name: "api_key".to_owned(),
+            key: Some(key.clone()),
+            hash: None,
+            scopes: vec![ApiScope::Admin],
+        });
+
+        return legacy.into_iter().chain(self.api_keys.iter().cloned()).collect();
+    }
+
+    /// Access policy of a read-only API route, routes without an entry in `access_policy` require the read scope,
+    /// except for those used by the instance page. These are public unless web login is enabled, then they require
+    /// being logged in or any API key.
+    ///
+    /// API v3 routes share the entry of their v2 counterpart, so a single entry covers both versions.
+    pub fn route_access(&self, path: &str) -> RouteAccess {
+        let path = match path {
+            "/api/v3/version" => "/api/version",
+            "/api/v3/instances/<id>" => "/api/instance",
+            "/api/v3/instances/<id>/logs" => "/api/logs",
+            "/api/v3/instances/<id>/players" => "/api/players",
+            "/api/v3/instances/<id>/events" => "/api/events",
+            // The stream of an instance only sends what the entries of the routes it replaces allow
+            "/api/v3/events/stream" => "/api/events",
+            path => path,
+        };
+        if let Some(access) = self.access_policy.get().get(path) {
+            return *access;
+        }
+
+        let instance_page = match *self.web_login {
+            WebLogin::Disabled => RouteAccess::Public,
+            _ => RouteAccess::AnyKey,
+        };
+        return match path {
+            "/api/logs" | "/api/players" | "/api/instance" => instance_page,
+            _ => RouteAccess::Scope(ApiScope::Read),
+        };
+    }
+
     pub fn load() -> Self {
         return std::fs::read_to_string("config.json").map(|json| serde_json::from_str(&json).unwrap()).unwrap_or_else(
             |_| {
@@ -10,3 +10,16 @@ macro_rules! hash_map(
         }
      };
 );
+
+#[macro_export]
+macro_rules! btree_map(
+    { $($key:expr => $value:expr),+ } => {
+        {
+            let mut m = ::std::collections::BTreeMap::new();
+            $(
+                m.insert($key, $value);
+            )+
+            m
+        }
+     };
+);
@@ -1,13 +1,21 @@
+use crate::config::{ApiScope, Hero, Preset};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+/// Version of the API directly under `/api`, newer versions are served under `/api/v<version>`
 pub const API_VERSION: u32 = 2;
+/// All versions served by this server
+pub const API_VERSIONS: &'static [u32] = &[2, 3];
 
 pub const EVENT_PLAYER_JOIN: &'static str = "PLAYER_JOIN";
 pub const EVENT_PLAYER_LOCK: &'static str = "PLAYER_LOCK";
+pub const EVENT_PLAYER_LEAVE: &'static str = "PLAYER_LEAVE";
+pub const EVENT_PLAYER_TEAM: &'static str = "PLAYER_TEAM";
 pub const EVENT_GUARDIAN_ATTACK: &'static str = "GUARDIAN_ATTACK";
 pub const EVENT_SERVER_READY: &'static str = "SERVER_READY";
 pub const EVENT_MATCH_STARTING: &'static str = "MATCH_STARTING";
 pub const EVENT_MATCH_FINISHED: &'static str = "MATCH_FINISHED";
+pub const EVENT_TEAM_VICTORY: &'static str = "TEAM_VICTORY";
 
 pub type InstanceID = usize;
 pub type Timestamp = u128;
@@ -31,10 +39,117 @@ pub struct EvenDataMatchStarting {
     pub open_url: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerSession {
+    pub joined_at: Timestamp,
+    pub left_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeroChange {
+    pub hero: String,
+    pub timestamp: Timestamp,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Player {
     pub name: String,
     pub hero: Option<String>,
+    /// Catalog entry of `hero`
+    #[serde(default)]
+    pub hero_info: Option<Hero>,
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default = "default_connected")]
+    pub connected: bool,
+    #[serde(default)]
+    pub joined_at: Option<Timestamp>,
+    #[serde(default)]
+    pub locked_at: Option<Timestamp>,
+    #[serde(default)]
+    pub hero_history: Vec<HeroChange>,
+    #[serde(default)]
+    pub sessions: Vec<PlayerSession>,
+}
+
+/// Records from before players leaving was detected have no flag, back then every player counted as connected.
+fn default_connected() -> bool {
+    return true;
+}
+
+/// Player names by team
+pub type TeamCompositions = BTreeMap<String, Vec<String>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TeamScore {
+    pub guardian_attacks: u32,
+    pub damage: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MatchScore {
+    pub teams: BTreeMap<String, TeamScore>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchResult {
+    pub winning_team: Option<String>,
+    pub started_at: Option<Timestamp>,
+    pub finished_at: Timestamp,
+    /// Milliseconds from `MATCH_STARTING` to the end of the match
+    pub duration: Option<Timestamp>,
+    pub map: String,
+    pub game_mod: Option<String>,
+    pub creatures: Vec<String>,
+    pub players: Vec<Player>,
+    #[serde(default)]
+    pub teams: TeamCompositions,
+    pub score: MatchScore,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub instance_id: InstanceID,
+    pub map: String,
+    pub creatures: Vec<String>,
+    pub max_players: usize,
+    pub game_mod: Option<String>,
+    pub started_at: Timestamp,
+    pub finished_at: Option<Timestamp>,
+    pub exit_code: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ExitReason {
+    Finished,
+    Killed,
+    Crashed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchRecord {
+    pub run: RunInfo,
+    pub result: MatchResult,
+    pub exit_reason: ExitReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeroStats {
+    pub hero: String,
+    pub matches: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerStats {
+    pub name: String,
+    pub matches_played: usize,
+    pub wins: usize,
+    pub losses: usize,
+    /// Heroes ordered by the number of matches they were played in
+    pub heroes: Vec<HeroStats>,
+    /// Total time connected to the server in milliseconds
+    pub time_played: Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -43,23 +158,136 @@ pub struct VersionResponse {
     pub app_version_major: String,
     pub app_version_minor: String,
     pub app_version_patch: String,
+    /// Newest supported API version
     pub api_version: u32,
+    #[serde(default)]
+    pub api_versions: Vec<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StartRequest {
-    pub map: String,
+    /// Name of a preset to start, the other fields override its settings
+    #[serde(default)]
+    pub preset: Option<String>,
+    pub map: Option<String>,
     pub max_players: Option<usize>,
     pub creature0: Option<String>,
     pub creature1: Option<String>,
     pub creature2: Option<String>,
     pub game_mod: Option<String>,
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StartResponse {
-    pub error: Option<String>,
     pub open_url: Option<String>,
+    /// Set when no instance was available and the request was queued instead
+    #[serde(default)]
+    pub ticket: Option<QueueTicket>,
+    #[serde(default)]
+    pub instance_id: Option<InstanceID>,
+    /// Grants kill, restart and admin password access to the started instance only, send it as `x-owner-token`
+    #[serde(default)]
+    pub owner_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresetResponse {
+    pub preset: Option<Preset>,
+}
+
+/// An API key without its secret.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// Keys from the config can't be rotated or revoked through the API.
+    pub configured: bool,
+    pub created_at: Option<Timestamp>,
+    pub last_used: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyResponse {
+    /// The new secret, this is the only time it is shown.
+    pub key: Option<String>,
+    pub info: Option<ApiKeyInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StartSpec {
+    pub map: String,
+    pub creatures: Vec<String>,
+    pub max_players: usize,
+    pub game_mod: Option<String>,
+    /// Replaces existing `Key=Value` settings in DefaultGame.ini
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledStart {
+    pub id: String,
+    pub name: String,
+    pub spec: StartSpec,
+    /// One-off start time
+    pub start_at: Option<Timestamp>,
+    /// Recurring start times as cron expression (`sec min hour day month weekday [year]`) in server local time
+    pub cron: Option<String>,
+    /// Minutes before each start during which an instance is held back for it
+    pub reserve_minutes: u64,
+    pub next_start: Option<Timestamp>,
+    pub last_started: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleRequest {
+    pub name: String,
+    #[serde(flatten)]
+    pub start: StartRequest,
+    pub start_at: Option<Timestamp>,
+    pub cron: Option<String>,
+    pub reserve_minutes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleResponse {
+    pub schedule: Option<ScheduledStart>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TicketState {
+    Waiting,
+    Started,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueTicket {
+    pub ticket: String,
+    pub state: TicketState,
+    /// 1-based position in the queue while waiting
+    pub position: Option<usize>,
+    pub instance_id: Option<InstanceID>,
+    pub open_url: Option<String>,
+    pub spec: StartSpec,
+    pub created_at: Timestamp,
+}
+
+/// A waiting start request as listed by `/api/queue`, without the ticket id which grants access to it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedStart {
+    /// 1-based position in the queue
+    pub position: Option<usize>,
+    pub spec: StartSpec,
+    pub created_at: Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -68,16 +296,42 @@ pub struct KillRequest {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct KillResponse {
-    pub error: Option<String>,
+pub struct KillResponse {}
+
+/// Body of all API error responses, sent with a 4xx or 5xx status.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorResponse {
+    /// Machine readable error, e.g. `not_found` or `too_many_requests`
+    pub code: String,
+    pub message: String,
+    /// Extra information depending on the error, e.g. `retry_after` in seconds for rate limits
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestartRequest {
+    pub id: InstanceID,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestartResponse {}
+
 #[derive(Deserialize)]
 pub struct PlayersResponse {
     pub name: String,
     pub hero: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceResponse {
+    pub instance_id: InstanceID,
+    pub run: Option<RunInfo>,
+    pub score: MatchScore,
+    pub players: Vec<Player>,
+    pub teams: TeamCompositions,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AdminPWRequest {
     pub id: InstanceID,
@@ -98,3 +352,63 @@ pub struct EventsResponse {
     pub timestamp: u128,
     pub events: Vec<Event>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Start,
+    Restart,
+    Kill,
+    AdminPw,
+    AddPreset,
+    RemovePreset,
+    AddSchedule,
+    RemoveSchedule,
+    CreateApiKey,
+    RotateApiKey,
+    RevokeApiKey,
+    Login,
+    /// The config was edited since the last start
+    ChangeConfig,
+    AddMod,
+    UpdateMod,
+    RemoveMod,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: Timestamp,
+    pub action: AuditAction,
+    pub client_ip: Option<String>,
+    /// Who performed the action, e.g. `key:<name>`, `user:<name>`, `owner` or `schedule:<name>`
+    pub actor: Option<String>,
+    pub instance_id: Option<InstanceID>,
+    pub params: serde_json::Value,
+    /// Set when the action failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditResponse {
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchesResponse {
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub matches: Vec<MatchRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunResponse {
+    pub run: RunInfo,
+    pub events: Vec<Event>,
+    pub players: Vec<Player>,
+    pub result: Option<MatchResult>,
+    pub files: Vec<String>,
+}
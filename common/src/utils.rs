@@ -1,8 +1,16 @@
+use crate::models::Timestamp;
 use std::{
     boxed::Box,
     ops::{Deref, DerefMut, Drop, FnMut},
+    time::SystemTime,
 };
 
+/// Milliseconds since the unix epoch.
+pub fn now() -> Timestamp {
+    let duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    return (duration.as_secs() as u128) * 1000 + duration.subsec_millis() as u128;
+}
+
 /// The DropGuard will remain to `Send` and `Sync` from `T`.
 ///
 /// # Examples
@@ -1,11 +1,22 @@
-use rgcp_common::config::Config;
+use crate::{
+    error::{reject, ApiError},
+    server_manager::ServerManager,
+    session::User,
+};
+use rgcp_common::{
+    config::{ApiKeyConfig, ApiScope, Config, RouteAccess},
+    models::InstanceID,
+};
 use rocket::{
-    http::Status,
     request::{self, FromRequest, Request, State},
     Outcome::{self, Success},
 };
+use std::sync::Arc;
+
+pub struct ApiKey(ApiKeyConfig);
 
-pub struct ApiKey(String);
+/// Key matching the `x-api-key` header, cached so that several guards of a request only verify it once.
+struct VerifiedKey(Option<ApiKeyConfig>);
 
 #[derive(Debug)]
 pub enum ApiKeyError {
@@ -13,25 +24,217 @@ pub enum ApiKeyError {
     BadCount,
     Missing,
     Invalid,
+    MissingScope(ApiScope),
+}
+
+impl ApiKeyError {
+    /// Fails a request guard with the status and message for this error.
+    fn reject<T>(self, request: &Request) -> request::Outcome<T, Self> {
+        let error = match &self {
+            ApiKeyError::InternalError => ApiError::internal("couldn't check the API key"),
+            ApiKeyError::BadCount => ApiError::bad_request("only one x-api-key header is allowed"),
+            ApiKeyError::Missing => ApiError::unauthorized("an API key is required, send it as x-api-key"),
+            ApiKeyError::Invalid => ApiError::unauthorized("invalid API key"),
+            ApiKeyError::MissingScope(scope) => ApiError::forbidden("the API key lacks the required scope")
+                .with_details(serde_json::json!({ "scope": scope })),
+        };
+
+        return reject(request, error, self);
+    }
+}
+
+impl ApiKey {
+    /// The valid key sent with a request, unlike the guard this doesn't fail the request if there is none.
+    pub fn sent_with(request: &Request) -> Option<ApiKey> {
+        let server_manager = request.guard::<State<Arc<ServerManager>>>().succeeded()?;
+        let keys: Vec<_> = request.headers().get("x-api-key").collect();
+        return match keys.as_slice() {
+            [key] => Self::verify(request, &server_manager, key),
+            _ => None,
+        };
+    }
+
+    fn verify(request: &Request, server_manager: &ServerManager, key: &str) -> Option<ApiKey> {
+        let verified = request.local_cache(|| VerifiedKey(server_manager.api_keys().verify(key)));
+        return verified.0.clone().map(ApiKey);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.0.has_scope(scope)
+    }
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for ApiKey {
     type Error = ApiKeyError;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        if let Success(config) = request.guard::<State<Config>>() {
-            if config.api_key.is_none() {
-                return Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid));
-            }
+        if let Success(server_manager) = request.guard::<State<Arc<ServerManager>>>() {
             let keys: Vec<_> = request.headers().get("x-api-key").collect();
             return match keys.len() {
-                0 => Outcome::Failure((Status::BadRequest, ApiKeyError::Missing)),
-                1 if Some(keys[0].to_owned()) == *config.api_key => Outcome::Success(ApiKey(keys[0].to_string())),
-                1 => Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid)),
-                _ => Outcome::Failure((Status::BadRequest, ApiKeyError::BadCount)),
+                0 => ApiKeyError::Missing.reject(request),
+                1 => match Self::verify(request, &server_manager, keys[0]) {
+                    Some(api_key) => Outcome::Success(api_key),
+                    None => ApiKeyError::Invalid.reject(request),
+                },
+                _ => ApiKeyError::BadCount.reject(request),
             };
         }
 
-        return Outcome::Failure((Status::BadRequest, ApiKeyError::InternalError));
+        return ApiKeyError::InternalError.reject(request);
+    }
+}
+
+/// Defines a request guard which only accepts API keys with the given scope.
+macro_rules! scoped_api_key {
+    ($name:ident, $scope:expr) => {
+        #[allow(dead_code)]
+        pub struct $name(pub ApiKey);
+
+        impl<'a, 'r> FromRequest<'a, 'r> for $name {
+            type Error = ApiKeyError;
+
+            fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+                return match ApiKey::from_request(request) {
+                    Success(api_key) if api_key.has_scope($scope) => Success($name(api_key)),
+                    Success(_) => ApiKeyError::MissingScope($scope).reject(request),
+                    Outcome::Failure(failure) => Outcome::Failure(failure),
+                    Outcome::Forward(forward) => Outcome::Forward(forward),
+                };
+            }
+        }
+    };
+}
+
+scoped_api_key!(ReadKey, ApiScope::Read);
+scoped_api_key!(StartKey, ApiScope::Start);
+scoped_api_key!(KillKey, ApiScope::Kill);
+scoped_api_key!(AdminPwKey, ApiScope::AdminPw);
+scoped_api_key!(ManageModsKey, ApiScope::ManageMods);
+scoped_api_key!(AdminKey, ApiScope::Admin);
+
+/// Request guard for read-only routes, the credentials it requires are configured per route in `access_policy`.
+pub struct ReadAccess;
+
+impl ReadAccess {
+    /// Checks the credentials the `access_policy` requires for the read-only route `path`.
+    fn check(request: &Request, path: &str) -> request::Outcome<Self, ApiKeyError> {
+        let access = match request.guard::<State<Config>>() {
+            Success(config) => config.route_access(path),
+            _ => return ApiKeyError::InternalError.reject(request),
+        };
+
+        let scope = match access {
+            RouteAccess::Public => return Success(ReadAccess),
+            RouteAccess::AnyKey if User::from_request(request).is_success() => return Success(ReadAccess),
+            RouteAccess::AnyKey => None,
+            RouteAccess::Scope(scope) => Some(scope),
+        };
+
+        return match (ApiKey::from_request(request), scope) {
+            (Success(api_key), Some(scope)) if !api_key.has_scope(scope) => {
+                ApiKeyError::MissingScope(scope).reject(request)
+            },
+            (Success(_), _) => Success(ReadAccess),
+            (Outcome::Failure(failure), _) => Outcome::Failure(failure),
+            (Outcome::Forward(forward), _) => Outcome::Forward(forward),
+        };
+    }
+
+    /// Like [`check`](Self::check) but doesn't fail the request.
+    fn allows(request: &Request, path: &str) -> bool {
+        let access = match request.guard::<State<Config>>() {
+            Success(config) => config.route_access(path),
+            _ => return false,
+        };
+
+        return match access {
+            RouteAccess::Public => true,
+            RouteAccess::AnyKey => User::from_request(request).is_success() || ApiKey::sent_with(request).is_some(),
+            RouteAccess::Scope(scope) => ApiKey::sent_with(request).map_or(false, |api_key| api_key.has_scope(scope)),
+        };
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReadAccess {
+    type Error = ApiKeyError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        return match request.route() {
+            Some(route) => Self::check(request, route.uri.path()),
+            None => ApiKeyError::InternalError.reject(request),
+        };
+    }
+}
+
+/// Request guard for the stream of an instance, which sends what several read-only routes return. Only the messages
+/// the `access_policy` entries of these routes allow are sent, the request fails if they allow none.
+#[derive(Clone, Copy)]
+pub struct StreamAccess {
+    /// Events, like `/api/events`
+    pub events: bool,
+    /// Log lines, like `/api/logs`
+    pub logs: bool,
+    /// Instance state including players and score, like `/api/instance` and `/api/players`
+    pub instance: bool,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for StreamAccess {
+    type Error = ApiKeyError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let access = StreamAccess {
+            events: ReadAccess::allows(request, "/api/events"),
+            logs: ReadAccess::allows(request, "/api/logs"),
+            instance: ReadAccess::allows(request, "/api/instance") && ReadAccess::allows(request, "/api/players"),
+        };
+        if access.events || access.logs || access.instance {
+            return Success(access);
+        }
+
+        // Fails with the error of a single route, e.g. when the API key is invalid
+        return ReadAccess::check(request, "/api/logs").map(|_| access);
+    }
+}
+
+/// Credentials for managing a single instance, either an API key or the owner token returned when it was started.
+pub struct InstanceAccess {
+    api_key: Option<ApiKey>,
+    owner_token: Option<String>,
+}
+
+impl InstanceAccess {
+    /// Whether the API key has `scope` or the owner token belongs to instance `id`.
+    pub fn allows(&self, server_manager: &ServerManager, id: InstanceID, scope: ApiScope) -> bool {
+        self.api_key.as_ref().map(|api_key| api_key.has_scope(scope)).unwrap_or(false)
+            || self.owner_token.as_ref().map(|token| server_manager.is_owner(id, token)).unwrap_or(false)
+    }
+
+    /// Like [`allows`](Self::allows) but fails with `403 Forbidden`.
+    pub fn check(&self, server_manager: &ServerManager, id: InstanceID, scope: ApiScope) -> Result<(), ApiError> {
+        if !self.allows(server_manager, id, scope) {
+            return Err(ApiError::forbidden(format!("no access to instance {}", id)));
+        }
+
+        return Ok(());
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for InstanceAccess {
+    type Error = ApiKeyError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let owner_token = request.headers().get_one("x-owner-token").map(str::to_owned);
+        let api_key = match ApiKey::from_request(request) {
+            Success(api_key) => Some(api_key),
+            Outcome::Failure((_, ApiKeyError::Missing)) if owner_token.is_some() => None,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(forward) => return Outcome::Forward(forward),
+        };
+
+        return Success(InstanceAccess { api_key, owner_token });
     }
 }
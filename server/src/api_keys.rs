@@ -0,0 +1,200 @@
+use crate::error::ApiError;
+use anyhow::{bail, Result};
+use rand::Rng;
+use rgcp_common::{
+    config::{ApiKeyConfig, ApiScope, Config},
+    models::{ApiKeyInfo, Timestamp},
+    utils::now,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env::current_dir, time::Duration};
+
+const HASH_ALGORITHM: &'static str = "sha256";
+
+/// How outdated the recorded last use of a key may get, so not every request writes to the database
+const LAST_USED_PRECISION: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+struct StoredApiKey {
+    name: String,
+    hash: String,
+    scopes: Vec<ApiScope>,
+    created_at: Timestamp,
+}
+
+/// API keys from the config and those created through the API.
+///
+/// Keys created through the API are only ever stored as salted hashes.
+pub struct ApiKeys {
+    configured: Vec<ApiKeyConfig>,
+    db: sled::Db,
+    last_used: sled::Tree,
+}
+
+impl ApiKeys {
+    pub fn new(config: &Config) -> Self {
+        let configured = config
+            .configured_api_keys()
+            .into_iter()
+            .filter(|api_key| match (&api_key.key, &api_key.hash) {
+                (None, None) => {
+                    log::error!("Ignoring API key {} without key or hash", api_key.name);
+                    false
+                },
+                (_, Some(hash)) if hash.split('$').count() != 3 => {
+                    log::error!("Ignoring API key {} with malformed hash", api_key.name);
+                    false
+                },
+                _ => true,
+            })
+            .collect();
+
+        let path = current_dir().unwrap().join(config.api_key_path.get());
+        let db = sled::open(path).expect("Cannot open API keys.");
+        let last_used = db.open_tree("last_used").expect("Cannot open API keys.");
+        ApiKeys { configured, db, last_used }
+    }
+
+    /// Finds the key matching `key` and records that it was used, at most once per [`LAST_USED_PRECISION`].
+    pub fn verify(&self, key: &str) -> Option<ApiKeyConfig> {
+        let configured = self.configured.iter().find(|api_key| match (&api_key.key, &api_key.hash) {
+            (_, Some(hash)) => verify_hash(hash, key),
+            (Some(plain), None) => constant_time_eq(plain.as_bytes(), key.as_bytes()),
+            (None, None) => false,
+        });
+
+        let api_key = match configured {
+            Some(api_key) => {
+                ApiKeyConfig { name: api_key.name.clone(), key: None, hash: None, scopes: api_key.scopes.clone() }
+            },
+            None => {
+                let stored = self.stored().into_iter().find(|api_key| verify_hash(&api_key.hash, key))?;
+                ApiKeyConfig { name: stored.name, key: None, hash: None, scopes: stored.scopes }
+            },
+        };
+
+        let now = now();
+        let stale = self
+            .last_used(&api_key.name)
+            .map_or(true, |last_used| now.saturating_sub(last_used) >= LAST_USED_PRECISION.as_millis());
+        if stale {
+            if let Ok(timestamp) = serde_cbor::to_vec(&now) {
+                let _ = self.last_used.insert(api_key.name.as_bytes(), timestamp);
+            }
+        }
+
+        return Some(api_key);
+    }
+
+    pub fn get_all(&self) -> Vec<ApiKeyInfo> {
+        let configured = self.configured.iter().map(|api_key| ApiKeyInfo {
+            name: api_key.name.clone(),
+            scopes: api_key.scopes.clone(),
+            configured: true,
+            created_at: None,
+            last_used: self.last_used(&api_key.name),
+        });
+
+        return configured.chain(self.stored().into_iter().map(|api_key| self.info(api_key))).collect();
+    }
+
+    /// Creates a new key, returns the secret which isn't stored anywhere.
+    pub fn create(&self, name: &str, scopes: Vec<ApiScope>) -> Result<(String, ApiKeyInfo)> {
+        if name.is_empty() {
+            bail!("key name is required");
+        }
+        if self.configured.iter().any(|api_key| api_key.name == name) || self.get(name).is_some() {
+            bail!(ApiError::conflict(format!("key {} already exists", name)));
+        }
+
+        let _ = self.last_used.remove(name.as_bytes());
+        return self.store(StoredApiKey { name: name.to_owned(), hash: String::new(), scopes, created_at: now() });
+    }
+
+    /// Replaces the secret of a key created through the API, keeping its name and scopes.
+    pub fn rotate(&self, name: &str) -> Result<(String, ApiKeyInfo)> {
+        match self.get(name) {
+            Some(api_key) => self.store(api_key),
+            None if self.configured.iter().any(|api_key| api_key.name == name) => {
+                bail!(ApiError::conflict(format!("key {} is defined in the config and can't be rotated", name)))
+            },
+            None => bail!(ApiError::not_found(format!("unknown key {}", name))),
+        }
+    }
+
+    /// Removes a key created through the API, keys from the config can't be revoked.
+    pub fn revoke(&self, name: &str) -> Option<ApiKeyInfo> {
+        let api_key = self.db.remove(name.as_bytes()).ok().flatten().and_then(|k| serde_cbor::from_slice(&k).ok());
+        let info = api_key.map(|api_key| self.info(api_key));
+        let _ = self.last_used.remove(name.as_bytes());
+        let _ = self.db.flush();
+        return info;
+    }
+
+    fn store(&self, mut api_key: StoredApiKey) -> Result<(String, ApiKeyInfo)> {
+        let key = generate_key();
+        api_key.hash = hash_key(&key);
+
+        self.db.insert(api_key.name.as_bytes(), serde_cbor::to_vec(&api_key)?)?;
+        self.db.flush()?;
+        return Ok((key, self.info(api_key)));
+    }
+
+    fn get(&self, name: &str) -> Option<StoredApiKey> {
+        self.db.get(name.as_bytes()).ok().flatten().and_then(|k| serde_cbor::from_slice(&k).ok())
+    }
+
+    fn stored(&self) -> Vec<StoredApiKey> {
+        self.db.iter().values().filter_map(|k| k.ok().and_then(|k| serde_cbor::from_slice(&k).ok())).collect()
+    }
+
+    fn last_used(&self, name: &str) -> Option<Timestamp> {
+        self.last_used.get(name.as_bytes()).ok().flatten().and_then(|t| serde_cbor::from_slice(&t).ok())
+    }
+
+    fn info(&self, api_key: StoredApiKey) -> ApiKeyInfo {
+        ApiKeyInfo {
+            last_used: self.last_used(&api_key.name),
+            name: api_key.name,
+            scopes: api_key.scopes,
+            configured: false,
+            created_at: Some(api_key.created_at),
+        }
+    }
+}
+
+/// Generates a new random API key.
+pub fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    return hex::encode(bytes);
+}
+
+/// Hashes a key with a random salt, the result has the form `sha256$<salt>$<hash>`.
+pub fn hash_key(key: &str) -> String {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let salt = hex::encode(salt);
+    return format!("{}${}${}", HASH_ALGORITHM, salt, hex::encode(digest(&salt, key)));
+}
+
+pub fn verify_hash(hash: &str, key: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    return match parts.as_slice() {
+        [algorithm, salt, hash] if *algorithm == HASH_ALGORITHM => match hex::decode(hash) {
+            Ok(hash) => constant_time_eq(&hash, &digest(salt, key)),
+            Err(_) => false,
+        },
+        _ => false,
+    };
+}
+
+fn digest(salt: &str, key: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(key.as_bytes());
+    return hasher.finalize().to_vec();
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use crate::log_buffer::read_lines;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use rgcp_common::{
+    config::{ArchiveRetention, Config},
+    models::*,
+    utils::now,
+};
+use std::{
+    env::current_dir,
+    fs,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const RUN_FILE: &'static str = "run.json";
+const EVENTS_FILE: &'static str = "events.json";
+const PLAYERS_FILE: &'static str = "players.json";
+const RESULT_FILE: &'static str = "result.json";
+const LOG_FILE: &'static str = "server.log.gz";
+#[cfg_attr(not(windows), allow(dead_code))]
+const CONFIG_FILES: [&'static str; 3] = ["DefaultGame.ini", "DefaultEngine.ini", "DefaultInput.ini"];
+
+lazy_static! {
+    static ref RE_ADMIN_PASSWORD: Regex = Regex::new(r#"(?m)^AdminPassword=[^\r\n]*"#).unwrap();
+}
+
+#[derive(Clone)]
+pub struct Archive {
+    path: PathBuf,
+    retention: ArchiveRetention,
+}
+
+impl Archive {
+    pub fn new(config: &Config) -> Self {
+        Archive {
+            path: current_dir().unwrap().join(config.archive_path.get()),
+            retention: config.archive_retention.get().clone(),
+        }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn store_run(
+        &self,
+        run: &RunInfo,
+        instance_path: &Path,
+        log_path: &Path,
+        events: &[Event],
+        result: &MatchResult,
+    ) -> Result<()> {
+        let run_path = self.path.join(&run.run_id);
+        fs::create_dir_all(&run_path).with_context(|| format!("Couldn't create {}", run_path.to_string_lossy()))?;
+
+        for file in CONFIG_FILES.iter() {
+            if instance_path.join(file).exists() {
+                // Archived runs can be read by anyone with the read scope, the admin password needs its own scope
+                let config = fs::read(instance_path.join(file))?;
+                fs::write(run_path.join(file), RE_ADMIN_PASSWORD.replace_all(&config, &b"AdminPassword="[..]))?;
+            }
+        }
+
+        if log_path.exists() {
+            let mut log = fs::File::open(log_path)?;
+            let mut encoder = GzEncoder::new(fs::File::create(run_path.join(LOG_FILE))?, Compression::default());
+            io::copy(&mut log, &mut encoder)?;
+            encoder.finish()?;
+        }
+
+        fs::write(run_path.join(EVENTS_FILE), serde_json::to_vec_pretty(events)?)?;
+        fs::write(run_path.join(PLAYERS_FILE), serde_json::to_vec_pretty(&result.players)?)?;
+        fs::write(run_path.join(RESULT_FILE), serde_json::to_vec_pretty(result)?)?;
+        fs::write(run_path.join(RUN_FILE), serde_json::to_vec_pretty(run)?)?;
+
+        self.enforce_retention();
+
+        return Ok(());
+    }
+
+    pub fn get_runs(&self) -> Vec<RunInfo> {
+        let mut runs: Vec<RunInfo> = fs::read_dir(&self.path)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .filter_map(|run_id| self.get_run(&run_id))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        runs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        return runs;
+    }
+
+    pub fn get_run(&self, run_id: &str) -> Option<RunInfo> {
+        self.read_json(run_id, RUN_FILE)
+    }
+
+    pub fn get_run_events(&self, run_id: &str) -> Vec<Event> {
+        self.read_json(run_id, EVENTS_FILE).unwrap_or_default()
+    }
+
+    pub fn get_run_players(&self, run_id: &str) -> Vec<Player> {
+        self.read_json(run_id, PLAYERS_FILE).unwrap_or_default()
+    }
+
+    pub fn get_run_result(&self, run_id: &str) -> Option<MatchResult> {
+        self.read_json(run_id, RESULT_FILE)
+    }
+
+    pub fn get_run_log(&self, run_id: &str) -> Option<String> {
+        let mut log = Vec::new();
+        self.open_run_log(run_id)?.read_to_end(&mut log).ok()?;
+        return Some(String::from_utf8_lossy(&log).into_owned());
+    }
+
+    pub fn get_run_log_lines(&self, run_id: &str, from_line: u64, to_line: u64) -> Option<Vec<String>> {
+        return Some(read_lines(BufReader::new(self.open_run_log(run_id)?), from_line, to_line));
+    }
+
+    pub fn get_run_files(&self, run_id: &str) -> Vec<String> {
+        self.run_path(run_id)
+            .and_then(|path| fs::read_dir(path).ok())
+            .map(|entries| {
+                let mut files: Vec<String> =
+                    entries.filter_map(Result::ok).filter_map(|entry| entry.file_name().into_string().ok()).collect();
+                files.sort();
+                files
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn run_file(&self, run_id: &str, file: &str) -> Option<PathBuf> {
+        if !is_valid_name(file) {
+            return None;
+        }
+
+        self.run_path(run_id).map(|path| path.join(file)).filter(|file| file.is_file())
+    }
+
+    pub fn enforce_retention(&self) {
+        let mut runs = self.get_runs();
+
+        if let Some(max_runs) = self.retention.max_runs {
+            while runs.len() > max_runs {
+                self.remove_run(runs.pop().unwrap());
+            }
+        }
+
+        if let Some(max_age_days) = self.retention.max_age_days {
+            let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60).as_millis();
+            let now = now();
+            while runs.last().map(|run| run.started_at + max_age < now).unwrap_or(false) {
+                self.remove_run(runs.pop().unwrap());
+            }
+        }
+
+        if let Some(max_size_mb) = self.retention.max_size_mb {
+            let max_size = max_size_mb * 1024 * 1024;
+            let mut sizes: Vec<u64> =
+                runs.iter().map(|run| self.run_path(&run.run_id).map(|path| dir_size(&path)).unwrap_or(0)).collect();
+            while sizes.iter().sum::<u64>() > max_size && !runs.is_empty() {
+                sizes.pop();
+                self.remove_run(runs.pop().unwrap());
+            }
+        }
+    }
+
+    fn open_run_log(&self, run_id: &str) -> Option<GzDecoder<fs::File>> {
+        self.run_file(run_id, LOG_FILE).and_then(|file| fs::File::open(file).ok()).map(GzDecoder::new)
+    }
+
+    fn remove_run(&self, run: RunInfo) {
+        if let Some(path) = self.run_path(&run.run_id) {
+            println!("Removing archived run {}", run.run_id);
+            let _ = fs::remove_dir_all(path);
+        }
+    }
+
+    fn run_path(&self, run_id: &str) -> Option<PathBuf> {
+        Some(self.path.join(run_id)).filter(|path| is_valid_name(run_id) && path.is_dir())
+    }
+
+    fn read_json<T: serde::de::DeserializeOwned>(&self, run_id: &str, file: &str) -> Option<T> {
+        self.run_file(run_id, file)
+            .and_then(|file| fs::read(file).ok())
+            .and_then(|json| serde_json::from_slice(&json).ok())
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !name.starts_with('.')
+}
+
+fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => 0,
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
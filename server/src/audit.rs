@@ -0,0 +1,190 @@
+use crate::{api_key::ApiKey, mods::get_mods, rate_limit::client_ip, session::User};
+use rgcp_common::{
+    config::Config,
+    models::{AuditAction, AuditEntry, InstanceID, Timestamp},
+    utils::now,
+};
+use rocket::{
+    request::{self, FromRequest, Request},
+    Outcome::Success,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env::current_dir,
+    fs,
+    path::PathBuf,
+};
+
+#[derive(Default)]
+pub struct AuditQuery {
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+    pub action: Option<AuditAction>,
+    pub actor: Option<String>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.from.map(|from| entry.timestamp >= from).unwrap_or(true)
+            && self.to.map(|to| entry.timestamp <= to).unwrap_or(true)
+            && self.action.map(|action| entry.action == action).unwrap_or(true)
+            && self.actor.as_ref().map(|actor| entry.actor.as_ref() == Some(actor)).unwrap_or(true)
+    }
+}
+
+/// Who sent a request, for the audit log.
+pub struct Actor {
+    pub client_ip: Option<String>,
+    pub name: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Actor {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        // Not the `ApiKey` guard, its failure would be reported instead of that of the route's own guards
+        let name = match (ApiKey::sent_with(request), request.guard::<User>()) {
+            (Some(api_key), _) => Some(format!("key:{}", api_key.name())),
+            (_, Success(user)) => Some(format!("user:{}", user.0)),
+            _ if request.headers().contains("x-owner-token") => Some("owner".to_owned()),
+            _ => None,
+        };
+
+        return Success(Actor { client_ip: client_ip(request).map(|ip| ip.to_string()), name });
+    }
+}
+
+/// Persistent trail of starts, kills and changes made through the API, the web UI or on disk.
+pub struct AuditLog {
+    db: sled::Db,
+}
+
+impl AuditLog {
+    pub fn new(config: &Config) -> Self {
+        let path = current_dir().unwrap().join(config.audit_log_path.get());
+        AuditLog { db: sled::open(path).expect("Cannot open audit log.") }
+    }
+
+    pub fn record(
+        &self,
+        action: AuditAction,
+        actor: &Actor,
+        instance_id: Option<InstanceID>,
+        params: impl Serialize,
+        error: Option<String>,
+    ) {
+        let entry = AuditEntry {
+            timestamp: now(),
+            action,
+            client_ip: actor.client_ip.clone(),
+            actor: actor.name.clone(),
+            instance_id,
+            params: serde_json::to_value(params).unwrap_or_default(),
+            error,
+        };
+
+        // Ids are monotonic, so entries are kept in the order they were recorded
+        let result = self.db.generate_id().and_then(|id| {
+            self.db.insert(id.to_be_bytes(), serde_cbor::to_vec(&entry).unwrap())?;
+            self.db.flush()
+        });
+        if let Err(e) = result {
+            log::error!("Couldn't record {:?} in the audit log: {}", action, e);
+        }
+    }
+
+    /// Returns the total number of matching entries and the requested page of them, newest first.
+    pub fn query(&self, query: &AuditQuery, page: usize, per_page: usize) -> (usize, Vec<AuditEntry>) {
+        let matching = self
+            .db
+            .iter()
+            .values()
+            .rev()
+            .filter_map(|entry| entry.ok().and_then(|entry| serde_cbor::from_slice(&entry).ok()))
+            .filter(|entry| query.matches(entry));
+
+        // Only the requested page is kept, the other entries are just counted
+        let skip = page.saturating_mul(per_page);
+        let mut total = 0;
+        let mut entries = Vec::new();
+        for entry in matching {
+            if total >= skip && entries.len() < per_page {
+                entries.push(entry);
+            }
+            total += 1;
+        }
+
+        return (total, entries);
+    }
+
+    /// Records changes to the config and mods since the last start, both are edited on disk rather than through the
+    /// API. Only the names of changed config keys are recorded, their values may be secrets.
+    pub fn record_changes(&self, config: &Config) {
+        let actor = Actor { client_ip: None, name: None };
+
+        let config_digests: BTreeMap<String, String> = match serde_json::to_value(config) {
+            Ok(Value::Object(values)) => values.iter().map(|(key, value)| (key.clone(), digest_json(value))).collect(),
+            _ => BTreeMap::new(),
+        };
+        if let Some(previous) = self.swap_snapshot("config", &config_digests) {
+            let changed: Vec<&String> = previous
+                .keys()
+                .chain(config_digests.keys())
+                .filter(|key| previous.get(*key) != config_digests.get(*key))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            if !changed.is_empty() {
+                self.record(AuditAction::ChangeConfig, &actor, None, json!({ "keys": changed }), None);
+            }
+        }
+
+        let mod_digests: BTreeMap<String, String> =
+            get_mods().into_iter().map(|game_mod| (game_mod.id.clone(), digest_mod(&game_mod.id))).collect();
+        if let Some(previous) = self.swap_snapshot("mods", &mod_digests) {
+            for (id, digest) in &mod_digests {
+                match previous.get(id) {
+                    None => self.record(AuditAction::AddMod, &actor, None, json!({ "id": id }), None),
+                    Some(previous) if previous != digest => {
+                        self.record(AuditAction::UpdateMod, &actor, None, json!({ "id": id }), None)
+                    },
+                    Some(_) => {},
+                }
+            }
+            for id in previous.keys().filter(|id| !mod_digests.contains_key(*id)) {
+                self.record(AuditAction::RemoveMod, &actor, None, json!({ "id": id }), None);
+            }
+        }
+    }
+
+    /// Stores a snapshot and returns the one it replaced, there is none on the first start.
+    fn swap_snapshot(&self, name: &str, snapshot: &BTreeMap<String, String>) -> Option<BTreeMap<String, String>> {
+        let snapshots = self.db.open_tree("snapshots").ok()?;
+        let previous = snapshots.insert(name.as_bytes(), serde_cbor::to_vec(snapshot).ok()?).ok().flatten();
+        let _ = snapshots.flush();
+        return previous.and_then(|previous| serde_cbor::from_slice(&previous).ok());
+    }
+}
+
+fn digest_json(value: &Value) -> String {
+    return hex::encode(Sha256::digest(value.to_string().as_bytes()));
+}
+
+/// Digest of all files of a mod, including the config files it replaces.
+fn digest_mod(id: &str) -> String {
+    let mut hasher = Sha256::new();
+    let mut files: Vec<PathBuf> = glob::glob(&format!("mods/{}/**/*", glob::Pattern::escape(id)))
+        .map(|paths| paths.filter_map(Result::ok).filter(|path| path.is_file()).collect())
+        .unwrap_or_default();
+    files.sort();
+
+    for file in files {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update(fs::read(&file).unwrap_or_default());
+    }
+
+    return hex::encode(hasher.finalize());
+}
@@ -0,0 +1,169 @@
+use rgcp_common::models::ErrorResponse;
+use rocket::{
+    catch,
+    http::{ContentType, Status},
+    request::{self, Request},
+    response::{self, Responder, Response},
+    Outcome,
+};
+use std::{fmt, io::Cursor, time::Duration};
+
+/// An error returned by the API, responds with its status and an [`ErrorResponse`] body.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+    /// Seconds until the request may be retried, sent as `Retry-After`
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError { status, code, message: message.into(), details: None, retry_after: None }
+    }
+
+    pub fn with_details(self, details: serde_json::Value) -> Self {
+        ApiError { details: Some(details), ..self }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(Status::BadRequest, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(Status::Unauthorized, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(Status::Forbidden, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Status::NotFound, "not_found", message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(Status::Conflict, "conflict", message)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::new(Status::UnprocessableEntity, "invalid_request", message)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after: Option<Duration>) -> Self {
+        let retry_after = retry_after.map(|retry_after| retry_after.as_secs() + 1);
+        let details = retry_after.map(|seconds| serde_json::json!({ "retry_after": seconds }));
+        return ApiError { details, retry_after, ..Self::new(Status::TooManyRequests, "too_many_requests", message) };
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(Status::ServiceUnavailable, "unavailable", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(Status::InternalServerError, "internal_error", message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Errors of the stores are invalid requests unless they already carry a more specific [`ApiError`].
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        error.downcast().unwrap_or_else(|error: anyhow::Error| ApiError::invalid(error.to_string()))
+    }
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        let code = match status.code {
+            400 => "bad_request",
+            401 => "unauthorized",
+            403 => "forbidden",
+            404 => "not_found",
+            409 => "conflict",
+            422 => "invalid_request",
+            429 => "too_many_requests",
+            503 => "unavailable",
+            _ => "internal_error",
+        };
+
+        return Self::new(status, code, status.reason.to_lowercase());
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let body = ErrorResponse { code: self.code.to_owned(), message: self.message, details: self.details };
+        let body = serde_json::to_string(&body).unwrap();
+
+        let mut response = Response::build();
+        response.status(self.status).header(ContentType::JSON).sized_body(Cursor::new(body));
+        if let Some(retry_after) = self.retry_after {
+            response.raw_header("Retry-After", retry_after.to_string());
+        }
+
+        return response.ok();
+    }
+}
+
+/// Remembers why a request guard failed so the catcher can report it.
+struct Rejected(Option<ApiError>);
+
+/// Fails a request guard with `error`, which is then sent by the catcher for its status.
+pub fn reject<T, E>(request: &Request, error: ApiError, reason: E) -> request::Outcome<T, E> {
+    let status = error.status;
+    request.local_cache(|| Rejected(Some(error)));
+    return Outcome::Failure((status, reason));
+}
+
+/// The error a guard rejected the request with, or a generic one for the status.
+pub fn caught(request: &Request, status: Status) -> ApiError {
+    return match request.local_cache(|| Rejected(None)) {
+        Rejected(Some(error)) if error.status == status => error.clone(),
+        _ => ApiError::from(status),
+    };
+}
+
+#[catch(400)]
+pub fn bad_request(request: &Request) -> ApiError {
+    caught(request, Status::BadRequest)
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request) -> ApiError {
+    caught(request, Status::Forbidden)
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> ApiError {
+    caught(request, Status::NotFound)
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request) -> ApiError {
+    caught(request, Status::UnprocessableEntity)
+}
+
+#[catch(429)]
+pub fn too_many_requests(request: &Request) -> ApiError {
+    caught(request, Status::TooManyRequests)
+}
+
+#[catch(500)]
+pub fn internal_error(request: &Request) -> ApiError {
+    caught(request, Status::InternalServerError)
+}
+
+#[catch(503)]
+pub fn unavailable(request: &Request) -> ApiError {
+    caught(request, Status::ServiceUnavailable)
+}
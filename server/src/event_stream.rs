@@ -0,0 +1,232 @@
+use crate::{api_key::StreamAccess, error::ApiError, server_manager::ServerManager};
+use rgcp_common::models::{InstanceID, Timestamp};
+use rocket::{
+    http::ContentType,
+    request::{self, FromRequest, Request},
+    response::{self, Responder, Response},
+    Outcome::Success,
+};
+use serde::Serialize;
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Streams are closed after this long so they don't hold a worker forever, clients reconnect and resume.
+const MAX_DURATION: Duration = Duration::from_secs(10 * 60);
+
+static OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// What a stream sends.
+#[derive(Clone, Copy)]
+pub enum Source {
+    /// Events of all instances
+    Events,
+    /// Events, log lines and the state of a single instance, as far as the client may read them
+    Instance(InstanceID, StreamAccess),
+}
+
+/// A stream of server-sent events, polls the server manager and sends whatever changed.
+pub struct EventStream {
+    server_manager: Arc<ServerManager>,
+    source: Source,
+    /// Timestamp of the last event sent
+    since: Timestamp,
+    /// Next log line to send
+    from_line: u64,
+    /// Last instance state sent
+    state: Option<String>,
+    buffer: Vec<u8>,
+    position: usize,
+    flushed: bool,
+    done: bool,
+    opened: Instant,
+    last_sent: Instant,
+}
+
+impl EventStream {
+    /// Opens a stream which resumes from `Last-Event-ID` when a client reconnects, from `since` and `from_line`
+    /// otherwise.
+    pub fn open(
+        server_manager: Arc<ServerManager>,
+        source: Source,
+        last_event_id: LastEventId,
+        since: Option<Timestamp>,
+        from_line: Option<u64>,
+        max_streams: usize,
+    ) -> Result<Self, ApiError> {
+        if OPEN_STREAMS.fetch_add(1, Ordering::SeqCst) >= max_streams {
+            OPEN_STREAMS.fetch_sub(1, Ordering::SeqCst);
+            return Err(ApiError::unavailable("too many open event streams"));
+        }
+
+        // Event ids are `<timestamp>-<line>`, see `cursor`
+        let cursor = last_event_id.0.and_then(|cursor| {
+            let mut parts = cursor.splitn(2, '-');
+            let since = parts.next()?.parse().ok()?;
+            Some((since, parts.next().and_then(|line| line.parse().ok())))
+        });
+        let (since, from_line) = match cursor {
+            Some((since, line)) => (Some(since), line.or(from_line)),
+            None => (since, from_line),
+        };
+
+        return Ok(EventStream {
+            server_manager,
+            source,
+            since: since.unwrap_or(0),
+            from_line: from_line.unwrap_or(0),
+            state: None,
+            // Tells clients how soon to reconnect once a stream was closed
+            buffer: b"retry: 2000\n\n".to_vec(),
+            position: 0,
+            flushed: false,
+            done: false,
+            opened: Instant::now(),
+            last_sent: Instant::now(),
+        });
+    }
+
+    fn cursor(&self) -> String {
+        match self.source {
+            Source::Events => self.since.to_string(),
+            Source::Instance(..) => format!("{}-{}", self.since, self.from_line),
+        }
+    }
+
+    /// Waits until there is something to send, a keep-alive is due or the stream should be closed.
+    fn fill(&mut self) {
+        self.buffer.clear();
+        self.position = 0;
+
+        while !self.done {
+            self.poll();
+            if !self.buffer.is_empty() {
+                break;
+            }
+
+            if self.opened.elapsed() >= MAX_DURATION {
+                self.done = true;
+            }
+            else if self.last_sent.elapsed() >= KEEP_ALIVE {
+                self.buffer.extend_from_slice(b": keep-alive\n\n");
+            }
+            else {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        self.last_sent = Instant::now();
+    }
+
+    fn poll(&mut self) {
+        let (id, access) = match self.source {
+            Source::Events => {
+                let mut events = self.server_manager.get_events(self.since);
+                events.sort_by_key(|event| event.timestamp);
+                for event in events {
+                    self.since = event.timestamp;
+                    self.send("event", &event);
+                }
+                return;
+            },
+            Source::Instance(id, access) => (id, access),
+        };
+
+        let instance = match self.server_manager.get_instance(id) {
+            Some(instance) => instance,
+            None => {
+                self.send("shutdown", &id);
+                self.done = true;
+                return;
+            },
+        };
+
+        if access.events {
+            for event in self.server_manager.get_instance_events(id, self.since) {
+                self.since = event.timestamp;
+                self.send("event", &event);
+            }
+        }
+
+        let lines = match access.logs {
+            true => self.server_manager.get_logs(id, self.from_line, std::u64::MAX),
+            false => Vec::new(),
+        };
+        if !lines.is_empty() {
+            self.from_line += lines.len() as u64;
+            self.send("log", &lines);
+        }
+
+        // Players and score are sent whenever any of them changed
+        let state = serde_json::to_string(&instance).ok();
+        if access.instance && state != self.state {
+            self.state = state;
+            self.send("instance", &instance);
+        }
+    }
+
+    fn send(&mut self, event: &str, data: &impl Serialize) {
+        let data = serde_json::to_string(data).unwrap_or_default();
+        let cursor = self.cursor();
+        let _ = write!(self.buffer, "id: {}\nevent: {}\ndata: {}\n\n", cursor, event, data);
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.buffer.len() {
+            // Rocket flushes the response when the body would block, so every message is sent right away
+            if !self.flushed {
+                self.flushed = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if self.done {
+                return Ok(0);
+            }
+
+            self.fill();
+            self.flushed = false;
+        }
+
+        let read = (&self.buffer[self.position..]).read(buf)?;
+        self.position += read;
+        return Ok(read);
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        OPEN_STREAMS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<'r> Responder<'r> for EventStream {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        return Response::build()
+            .header(ContentType::new("text", "event-stream"))
+            .raw_header("Cache-Control", "no-cache")
+            .raw_header("X-Accel-Buffering", "no")
+            .chunked_body(self, 4096)
+            .ok();
+    }
+}
+
+/// The `Last-Event-ID` header sent by clients when they reconnect to a stream.
+pub struct LastEventId(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        return Success(LastEventId(request.headers().get_one("Last-Event-ID").map(str::to_owned)));
+    }
+}
@@ -1,15 +1,39 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 mod api_key;
+mod api_keys;
+mod archive;
 mod assets;
+mod audit;
 mod embed_file;
+mod error;
+mod event_stream;
+mod log_buffer;
+mod log_rules;
+mod log_tail;
+mod match_history;
+mod match_tracker;
+mod passwords;
 mod routes;
+mod scheduler;
 mod server_manager;
+mod session;
+mod start_queue;
 mod templates;
 mod mods;
+mod presets;
+mod rate_limit;
 
+pub use crate::{
+    api_keys::{generate_key, hash_key},
+    passwords::hash_password,
+};
 use crate::{assets::Assets, server_manager::ServerManager};
-use rgcp_common::{config::Config, AppInfo};
+use rand::Rng;
+use rgcp_common::{
+    config::{Config, WebLogin},
+    AppInfo,
+};
 use rocket::{config::Environment, fairing::AdHoc};
 use std::sync::Arc;
 
@@ -68,15 +92,28 @@ pub async fn run(config: Config) {
 
     setup_conf(&mut rocket_conf);
 
-    let server_manager = ServerManager::new(config.clone()).unwrap();
+    // Every open event stream blocks a worker, keep enough around for everything else
+    rocket_conf.workers += *config.max_event_streams.get() as u16;
+
+    if let Some(secret_key) = config.secret_key.get() {
+        rocket_conf.set_secret_key(secret_key.clone()).expect("Invalid secret_key, expected 256-bit base64.");
+    }
+    else if *config.web_login != WebLogin::Disabled {
+        println!("No secret_key set in your config.json, web UI logins won't survive a restart.");
+        rocket_conf.set_secret_key(base64::encode(rand::thread_rng().gen::<[u8; 32]>())).unwrap();
+    }
+
+    let server_manager = Arc::new(ServerManager::new(config.clone()).unwrap());
+    ServerManager::start_worker(&server_manager);
 
     rocket::custom(rocket_conf)
         .mount("/", routes::get())
+        .register(routes::catchers())
         .attach(AdHoc::on_attach("Assets Config", |rocket| {
             let assets_dir = rocket.config().get_str("static_dir").unwrap_or("assets/static").to_owned();
             Ok(rocket.manage(routes::static_files::StaticDir(assets_dir)))
         }))
         .manage(config)
-        .manage(Arc::new(server_manager))
+        .manage(server_manager)
         .launch();
 }
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, io::BufRead};
+
+/// Keeps the most recent lines of a log, bounded by line count and total size.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct LogBuffer {
+    lines: VecDeque<String>,
+    first_line: u64,
+    bytes: usize,
+    max_lines: usize,
+    max_bytes: usize,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl LogBuffer {
+    pub fn new(max_lines: usize, max_bytes: usize) -> Self {
+        LogBuffer { lines: VecDeque::new(), first_line: 0, bytes: 0, max_lines, max_bytes }
+    }
+
+    /// Line number of the oldest line still held in memory.
+    pub fn first_line(&self) -> u64 {
+        self.first_line
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.bytes += line.len();
+        self.lines.push_back(line);
+
+        while self.lines.len() > self.max_lines || (self.bytes > self.max_bytes && self.lines.len() > 1) {
+            if let Some(line) = self.lines.pop_front() {
+                self.bytes -= line.len();
+                self.first_line += 1;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.first_line = 0;
+        self.bytes = 0;
+    }
+
+    /// Returns the buffered lines in `from_line..to_line`, skipping lines that were already evicted.
+    pub fn get(&self, from_line: u64, to_line: u64) -> Vec<String> {
+        let from = from_line.saturating_sub(self.first_line) as usize;
+        let to = to_line.saturating_sub(self.first_line) as usize;
+        self.lines.iter().skip(from).take(to.saturating_sub(from)).cloned().collect()
+    }
+}
+
+/// Reads the lines in `from_line..to_line` from a log stored on disk.
+pub fn read_lines(reader: impl BufRead, from_line: u64, to_line: u64) -> Vec<String> {
+    reader
+        .split(b'\n')
+        .filter_map(Result::ok)
+        .skip(from_line as usize)
+        .take(to_line.saturating_sub(from_line) as usize)
+        .map(|line| String::from_utf8_lossy(&line).trim_end_matches('\r').to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(max_lines: usize, max_bytes: usize, lines: u64) -> LogBuffer {
+        let mut buffer = LogBuffer::new(max_lines, max_bytes);
+        for line in 0..lines {
+            buffer.push(format!("line {}", line));
+        }
+        buffer
+    }
+
+    #[test]
+    fn keeps_all_lines_below_the_limits() {
+        let buffer = buffer(10, 1024, 3);
+        assert_eq!(buffer.first_line(), 0);
+        assert_eq!(buffer.get(0, u64::MAX), vec!["line 0", "line 1", "line 2"]);
+        assert_eq!(buffer.get(1, 2), vec!["line 1"]);
+        assert!(buffer.get(3, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn evicts_oldest_lines_beyond_max_lines() {
+        let buffer = buffer(3, 1024, 5);
+        assert_eq!(buffer.first_line(), 2);
+        assert_eq!(buffer.get(2, u64::MAX), vec!["line 2", "line 3", "line 4"]);
+        assert_eq!(buffer.get(3, 4), vec!["line 3"]);
+    }
+
+    #[test]
+    fn evicts_oldest_lines_beyond_max_bytes() {
+        // Every line is 6 bytes long
+        let buffer = buffer(100, 13, 5);
+        assert_eq!(buffer.first_line(), 3);
+        assert_eq!(buffer.get(0, u64::MAX), vec!["line 3", "line 4"]);
+    }
+
+    #[test]
+    fn keeps_the_last_line_even_if_too_large() {
+        let mut buffer = buffer(100, 4, 2);
+        assert_eq!(buffer.get(0, u64::MAX), vec!["line 1"]);
+
+        buffer.push("a much longer line".to_owned());
+        assert_eq!(buffer.first_line(), 2);
+        assert_eq!(buffer.get(2, u64::MAX), vec!["a much longer line"]);
+    }
+
+    #[test]
+    fn skips_lines_older_than_the_buffer() {
+        let buffer = buffer(3, 1024, 10);
+        assert_eq!(buffer.first_line(), 7);
+        assert_eq!(buffer.get(0, u64::MAX), vec!["line 7", "line 8", "line 9"]);
+        assert_eq!(buffer.get(5, 9), vec!["line 7", "line 8"]);
+        assert!(buffer.get(0, 7).is_empty());
+        assert!(buffer.get(0, 3).is_empty());
+    }
+
+    #[test]
+    fn clear_starts_over() {
+        let mut buffer = buffer(3, 1024, 10);
+        buffer.clear();
+        assert_eq!(buffer.first_line(), 0);
+        assert!(buffer.get(0, u64::MAX).is_empty());
+
+        buffer.push("new".to_owned());
+        assert_eq!(buffer.get(0, u64::MAX), vec!["new"]);
+    }
+
+    #[test]
+    fn reads_line_ranges_from_disk() {
+        let log = "zero\r\none\ntwo\r\nthree";
+        assert_eq!(read_lines(log.as_bytes(), 0, u64::MAX), vec!["zero", "one", "two", "three"]);
+        assert_eq!(read_lines(log.as_bytes(), 1, 3), vec!["one", "two"]);
+        assert!(read_lines(log.as_bytes(), 4, u64::MAX).is_empty());
+        assert!(read_lines(log.as_bytes(), 2, 1).is_empty());
+    }
+}
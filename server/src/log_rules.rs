@@ -0,0 +1,169 @@
+use regex::Regex;
+use rgcp_common::config::{Hero, LogRule};
+use std::collections::BTreeMap;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct ParsedEvent {
+    pub id: String,
+    pub description: String,
+    pub data: BTreeMap<String, String>,
+}
+
+struct CompiledRule {
+    event: String,
+    regex: Regex,
+    description: String,
+    data: BTreeMap<String, String>,
+}
+
+/// Turns log lines into events according to a set of [`LogRule`]s.
+///
+/// Descriptions and data fields are templates which may reference capture groups of the rule's pattern by index
+/// (`$1`) or by name (`${name}`). Events with a `hero` data field also get a `hero_name` field with its display
+/// name, which descriptions may reference as `${hero_name}`.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct LogParser {
+    rules: Vec<CompiledRule>,
+    heroes: Vec<Hero>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl LogParser {
+    pub fn new<'a>(rules: impl IntoIterator<Item = &'a LogRule>, heroes: &[Hero]) -> Self {
+        let rules = rules
+            .into_iter()
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some(CompiledRule {
+                    event: rule.event.clone(),
+                    regex: regex,
+                    description: rule.description.clone(),
+                    data: rule.data.clone(),
+                }),
+                Err(e) => {
+                    log::error!("Invalid pattern for log rule {}: {}", rule.event, e);
+                    None
+                },
+            })
+            .collect();
+
+        LogParser { rules, heroes: heroes.to_vec() }
+    }
+
+    pub fn parse(&self, line: &str) -> Vec<ParsedEvent> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let captures = rule.regex.captures(line)?;
+                let expand = |template: &str| {
+                    let mut expanded = String::new();
+                    captures.expand(template, &mut expanded);
+                    expanded
+                };
+
+                let mut description = rule.description.clone();
+                let mut data: BTreeMap<String, String> =
+                    rule.data.iter().map(|(field, template)| (field.clone(), expand(template))).collect();
+                if let Some(hero_id) = data.get("hero") {
+                    let hero_name = Hero::find(&self.heroes, hero_id).name;
+                    // `$$` expands to a literal `$`
+                    description = description.replace("${hero_name}", &hero_name.replace('$', "$$"));
+                    data.insert("hero_name".to_owned(), hero_name);
+                }
+
+                Some(ParsedEvent { id: rule.event.clone(), description: expand(&description), data })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rgcp_common::{
+        config::{builtin_log_rules, Config},
+        models::*,
+    };
+
+    fn parse(line: &str) -> Vec<ParsedEvent> {
+        LogParser::new(&builtin_log_rules(), &Config::default().heroes).parse(line)
+    }
+
+    fn parse_one(line: &str) -> ParsedEvent {
+        let mut events = parse(line);
+        assert_eq!(events.len(), 1, "expected one event for {:?}", line);
+        events.remove(0)
+    }
+
+    #[test]
+    fn player_join() {
+        let event = parse_one("[0042.17] DevNet: Join succeeded: Alice Smith playerid=0x0000000000000012");
+        assert_eq!(event.id, EVENT_PLAYER_JOIN);
+        assert_eq!(event.description, "Player Alice Smith joined");
+        assert_eq!(event.data["name"], "Alice Smith");
+
+        assert!(parse("[0042.17] DevNet: Join request: Alice Smith playerid=0x0000000000000012").is_empty());
+    }
+
+    #[test]
+    fn player_lock() {
+        let event = parse_one(
+            "[0101.50] ScriptLog: RxPlayerController::PlayerWaiting:FinishLockingCharacterSelection UncleSvenFan \
+             LOCKED HeroProviderIndex:'3' RxGameContent.RxPawn_UncleSven",
+        );
+        assert_eq!(event.id, EVENT_PLAYER_LOCK);
+        assert_eq!(event.description, "Player UncleSvenFan locked char Uncle Sven");
+        assert_eq!(event.data["name"], "UncleSvenFan");
+        assert_eq!(event.data["hero"], "UncleSven");
+        assert_eq!(event.data["hero_name"], "Uncle Sven");
+
+        // Heroes missing from the catalog keep their id
+        let event = parse_one(
+            "[0101.50] ScriptLog: RxPlayerController::PlayerWaiting:FinishLockingCharacterSelection Alice LOCKED \
+             HeroProviderIndex:'3' RxGameContent.RxPawn_IceMage",
+        );
+        assert_eq!(event.description, "Player Alice locked char IceMage");
+        assert_eq!(event.data["hero_name"], "IceMage");
+
+        assert!(parse(
+            "[0101.50] ScriptLog: RxPlayerController::PlayerWaiting:FinishLockingCharacterSelection Alice UNLOCKED \
+             HeroProviderIndex:'3' RxGameContent.RxPawn_IceMage"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn match_starting() {
+        let event = parse_one("[0120.00] Log: Starting match...");
+        assert_eq!(event.id, EVENT_MATCH_STARTING);
+        assert_eq!(event.description, "Match starting");
+        assert!(event.data.is_empty());
+
+        assert!(parse("[0120.00] Log: Waiting for players").is_empty());
+    }
+
+    #[test]
+    fn unrelated_lines() {
+        assert!(parse("").is_empty());
+        assert!(parse("[0000.31] Init: Version: 11283").is_empty());
+        assert!(parse("[0010.02] Log: Bringing World RxEntry.TheWorld up for play").is_empty());
+    }
+
+    #[test]
+    fn configured_rules_follow_builtin_ones() {
+        let custom = LogRule {
+            event: "ROUND".to_owned(),
+            pattern: r#"Round (\d+) started"#.to_owned(),
+            description: "Round $1".to_owned(),
+            data: BTreeMap::new(),
+        };
+        let invalid = LogRule { pattern: "(".to_owned(), ..custom.clone() };
+        let rules = builtin_log_rules();
+        let parser = LogParser::new(rules.iter().chain(&[invalid, custom]), &[]);
+
+        let events = parser.parse("[0300.00] Log: Round 2 started");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, "ROUND");
+        assert_eq!(events[0].description, "Round 2");
+        assert_eq!(parser.parse("[0120.00] Log: Starting match...")[0].id, EVENT_MATCH_STARTING);
+    }
+}
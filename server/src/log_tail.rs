@@ -0,0 +1,244 @@
+use notify::{raw_watcher, Op, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum LogChange {
+    /// The log file was truncated or replaced, all previously read lines are gone.
+    Reset,
+    Line(String),
+}
+
+/// Follows a log file while it is being written.
+///
+/// Changes are picked up through file system notifications (inotify on Linux, ReadDirectoryChangesW on Windows),
+/// if those are unavailable the file is polled instead.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct LogTail {
+    path: PathBuf,
+    position: u64,
+    partial: Vec<u8>,
+    replaced: bool,
+    notifications: Option<(RecommendedWatcher, Receiver<RawEvent>)>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl LogTail {
+    pub fn new(path: &Path) -> Self {
+        let notifications = Self::watch(path)
+            .map_err(|e| log::warn!("Couldn't watch {}, falling back to polling: {}", path.to_string_lossy(), e))
+            .ok();
+
+        LogTail { path: path.to_owned(), position: 0, partial: Vec::new(), replaced: false, notifications }
+    }
+
+    /// Blocks until the log file was changed or `timeout` has elapsed.
+    pub fn wait(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut changed = false;
+
+        while !changed {
+            let received = match &self.notifications {
+                Some((_, receiver)) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => {
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    return;
+                },
+            };
+
+            match received {
+                Ok(event) => changed = self.handle_event(event),
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => self.notifications = None,
+            }
+        }
+
+        // Collect everything else that happened in the meantime, so a burst of writes is read in one go
+        if let Some((_, receiver)) = &self.notifications {
+            let pending: Vec<RawEvent> = receiver.try_iter().collect();
+            for event in pending {
+                self.handle_event(event);
+            }
+        }
+    }
+
+    /// Reads all lines that were completed since the last call.
+    pub fn read(&mut self) -> Vec<LogChange> {
+        let mut changes = Vec::new();
+
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return changes,
+        };
+
+        let size = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return changes,
+        };
+
+        if self.replaced || size < self.position {
+            if self.position > 0 || !self.partial.is_empty() {
+                changes.push(LogChange::Reset);
+            }
+
+            self.replaced = false;
+            self.position = 0;
+            self.partial.clear();
+        }
+
+        if size == self.position || file.seek(SeekFrom::Start(self.position)).is_err() {
+            return changes;
+        }
+
+        if let Ok(read) = file.take(size - self.position).read_to_end(&mut self.partial) {
+            self.position += read as u64;
+        }
+
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            changes.push(LogChange::Line(String::from_utf8_lossy(&line[..end]).trim_end_matches('\r').to_owned()));
+        }
+
+        return changes;
+    }
+
+    fn watch(path: &Path) -> notify::Result<(RecommendedWatcher, Receiver<RawEvent>)> {
+        let (sender, receiver) = channel();
+        let mut watcher = raw_watcher(sender)?;
+
+        // Watch the directory rather than the file itself, so we notice when it is (re)created
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        return Ok((watcher, receiver));
+    }
+
+    fn handle_event(&mut self, event: RawEvent) -> bool {
+        let is_log = event.path.as_ref().and_then(|path| path.file_name()) == self.path.file_name();
+        if !is_log {
+            return false;
+        }
+
+        if let Ok(op) = event.op {
+            if op.intersects(Op::CREATE | Op::REMOVE | Op::RENAME) {
+                self.replaced = true;
+            }
+        }
+
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    struct TestLog {
+        dir: PathBuf,
+        path: PathBuf,
+    }
+
+    impl TestLog {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rgcp_log_tail_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("Launch.log");
+            return TestLog { dir, path };
+        }
+
+        fn append(&self, data: &str) {
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).unwrap();
+            file.write_all(data.as_bytes()).unwrap();
+        }
+    }
+
+    impl Drop for TestLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn lines(changes: Vec<LogChange>) -> Vec<String> {
+        return changes
+            .into_iter()
+            .map(|change| match change {
+                LogChange::Reset => "<reset>".to_owned(),
+                LogChange::Line(line) => line,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn reads_appended_lines() {
+        let log = TestLog::new("append");
+        let mut tail = LogTail::new(&log.path);
+        assert!(lines(tail.read()).is_empty());
+
+        log.append("first\r\nsecond\nthi");
+        assert_eq!(lines(tail.read()), vec!["first", "second"]);
+        assert!(lines(tail.read()).is_empty());
+
+        log.append("rd\nfourth\n");
+        assert_eq!(lines(tail.read()), vec!["third", "fourth"]);
+    }
+
+    #[test]
+    fn resets_when_truncated() {
+        let log = TestLog::new("truncate");
+        let mut tail = LogTail::new(&log.path);
+        log.append("first\nsecond\n");
+        assert_eq!(lines(tail.read()), vec!["first", "second"]);
+
+        fs::write(&log.path, "new\n").unwrap();
+        assert_eq!(lines(tail.read()), vec!["<reset>", "new"]);
+
+        log.append("more\n");
+        assert_eq!(lines(tail.read()), vec!["more"]);
+    }
+
+    #[test]
+    fn resets_when_replaced() {
+        let log = TestLog::new("replace");
+        let mut tail = LogTail::new(&log.path);
+        log.append("first\n");
+        assert_eq!(lines(tail.read()), vec!["first"]);
+
+        // The new file is larger than the old one, so only the notification tells it apart from appended data
+        fs::remove_file(&log.path).unwrap();
+        log.append("second\nthird\n");
+        tail.handle_event(RawEvent { path: Some(log.path.clone()), op: Ok(Op::CREATE), cookie: None });
+        assert_eq!(lines(tail.read()), vec!["<reset>", "second", "third"]);
+    }
+
+    #[test]
+    fn ignores_events_for_other_files() {
+        let log = TestLog::new("other");
+        let mut tail = LogTail::new(&log.path);
+        log.append("first\n");
+        assert_eq!(lines(tail.read()), vec!["first"]);
+
+        let other = RawEvent { path: Some(log.dir.join("Launch-backup.log")), op: Ok(Op::CREATE), cookie: None };
+        assert!(!tail.handle_event(other));
+        log.append("second\n");
+        assert_eq!(lines(tail.read()), vec!["second"]);
+    }
+
+    #[test]
+    fn waits_for_missing_file() {
+        let log = TestLog::new("missing");
+        let mut tail = LogTail::new(&log.path);
+        assert!(lines(tail.read()).is_empty());
+
+        log.append("first\n");
+        assert_eq!(lines(tail.read()), vec!["first"]);
+    }
+}
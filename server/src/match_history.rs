@@ -0,0 +1,117 @@
+use rgcp_common::{config::Config, models::*};
+use std::{collections::BTreeMap, env::current_dir};
+
+#[derive(Default)]
+pub struct MatchQuery {
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+    pub map: Option<String>,
+    pub player: Option<String>,
+}
+
+impl MatchQuery {
+    fn matches(&self, record: &MatchRecord) -> bool {
+        self.from.map(|from| record.run.started_at >= from).unwrap_or(true)
+            && self.to.map(|to| record.run.started_at <= to).unwrap_or(true)
+            && self.map.as_ref().map(|map| record.run.map.eq_ignore_ascii_case(map)).unwrap_or(true)
+            && self
+                .player
+                .as_ref()
+                .map(|player| record.result.players.iter().any(|p| p.name.eq_ignore_ascii_case(player)))
+                .unwrap_or(true)
+    }
+}
+
+/// Persistent record of every finished match.
+///
+/// Unlike the archive this is never pruned, it only holds the match summaries and not the logs or configs.
+#[derive(Clone)]
+pub struct MatchHistory {
+    db: sled::Db,
+}
+
+impl MatchHistory {
+    pub fn new(config: &Config) -> Self {
+        let path = current_dir().unwrap().join(config.match_history_path.get());
+        MatchHistory { db: sled::open(path).expect("Cannot open match history.") }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn record(&self, record: &MatchRecord) -> sled::Result<()> {
+        self.db.insert(record.run.run_id.as_bytes(), serde_cbor::to_vec(record).unwrap())?;
+        self.db.flush()?;
+        return Ok(());
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<MatchRecord> {
+        self.db.get(run_id.as_bytes()).ok().flatten().and_then(|record| serde_cbor::from_slice(&record).ok())
+    }
+
+    /// Returns the total number of matching records and the requested page of them, newest first.
+    pub fn query(&self, query: &MatchQuery, page: usize, per_page: usize) -> (usize, Vec<MatchRecord>) {
+        let records: Vec<MatchRecord> = self
+            .db
+            .iter()
+            .values()
+            .rev()
+            .filter_map(|record| record.ok().and_then(|record| serde_cbor::from_slice(&record).ok()))
+            .filter(|record| query.matches(record))
+            .collect();
+
+        let total = records.len();
+        return (total, records.into_iter().skip(page.saturating_mul(per_page)).take(per_page).collect());
+    }
+
+    pub fn player_stats(&self, name: &str) -> Option<PlayerStats> {
+        self.all_player_stats().into_iter().find(|stats| stats.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Statistics of all players, ordered by wins and then matches played.
+    pub fn leaderboard(&self) -> Vec<PlayerStats> {
+        let mut stats = self.all_player_stats();
+        stats.sort_by(|a, b| b.wins.cmp(&a.wins).then(b.matches_played.cmp(&a.matches_played)));
+        return stats;
+    }
+
+    fn all_player_stats(&self) -> Vec<PlayerStats> {
+        let mut players: BTreeMap<String, (PlayerStats, BTreeMap<String, usize>)> = BTreeMap::new();
+
+        let records = self.db.iter().values().filter_map(|record| record.ok());
+        for record in records.filter_map(|record| serde_cbor::from_slice::<MatchRecord>(&record).ok()) {
+            let result = &record.result;
+            for player in &result.players {
+                let (stats, heroes) = players.entry(player.name.clone()).or_default();
+                stats.matches_played += 1;
+
+                if let (Some(winning_team), Some(team)) = (&result.winning_team, &player.team) {
+                    if winning_team == team {
+                        stats.wins += 1;
+                    }
+                    else {
+                        stats.losses += 1;
+                    }
+                }
+
+                if let Some(hero) = &player.hero {
+                    *heroes.entry(hero.clone()).or_default() += 1;
+                }
+
+                stats.time_played += player
+                    .sessions
+                    .iter()
+                    .map(|session| session.left_at.unwrap_or(result.finished_at).saturating_sub(session.joined_at))
+                    .sum::<Timestamp>();
+            }
+        }
+
+        return players
+            .into_iter()
+            .map(|(name, (stats, heroes))| {
+                let mut heroes: Vec<HeroStats> =
+                    heroes.into_iter().map(|(hero, matches)| HeroStats { hero, matches }).collect();
+                heroes.sort_by(|a, b| b.matches.cmp(&a.matches));
+                PlayerStats { name, heroes, ..stats }
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::log_rules::ParsedEvent;
+use rgcp_common::{config::Hero, models::*};
+use std::collections::BTreeMap;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum MatchUpdate<'a> {
+    Player(&'a Player),
+    Score(&'a MatchScore),
+}
+
+/// Keeps track of the state of a running match, based on the events parsed from its log.
+#[derive(Default)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct MatchTracker {
+    heroes: Vec<Hero>,
+    players: BTreeMap<String, Player>,
+    score: MatchScore,
+    started_at: Option<Timestamp>,
+    winning_team: Option<String>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl MatchTracker {
+    pub fn new(heroes: Vec<Hero>) -> Self {
+        MatchTracker { heroes, ..MatchTracker::default() }
+    }
+
+    /// Applies an event to the match state, returns the part of it that changed, if any.
+    pub fn apply(&mut self, event: &ParsedEvent, timestamp: Timestamp) -> Option<MatchUpdate<'_>> {
+        match event.id.as_str() {
+            EVENT_GUARDIAN_ATTACK => {
+                let team = self.score.teams.entry(event.data.get("team")?.clone()).or_default();
+                team.guardian_attacks += 1;
+                team.damage += event.data.get("damage").and_then(|damage| damage.parse::<f64>().ok()).unwrap_or(0.0);
+                return Some(MatchUpdate::Score(&self.score));
+            },
+            EVENT_MATCH_STARTING => {
+                self.started_at = Some(timestamp);
+                return None;
+            },
+            EVENT_TEAM_VICTORY => {
+                self.winning_team = event.data.get("team").cloned();
+                return None;
+            },
+            _ => {},
+        }
+
+        let name = event.data.get("name")?;
+
+        let player = match event.id.as_str() {
+            EVENT_PLAYER_JOIN => {
+                let player = self.player(name);
+                player.connected = true;
+                player.joined_at.get_or_insert(timestamp);
+                player.sessions.push(PlayerSession { joined_at: timestamp, left_at: None });
+                player
+            },
+            EVENT_PLAYER_LOCK => {
+                let hero = event.data.get("hero")?;
+                let hero_info = Hero::find(&self.heroes, hero);
+                let player = self.player(name);
+                if player.hero.as_ref() != Some(hero) {
+                    player.hero_history.push(HeroChange { hero: hero.clone(), timestamp });
+                }
+                player.hero = Some(hero.clone());
+                player.hero_info = Some(hero_info);
+                player.locked_at = Some(timestamp);
+                player
+            },
+            EVENT_PLAYER_TEAM => {
+                let player = self.player(name);
+                player.team = event.data.get("team").cloned();
+                player
+            },
+            EVENT_PLAYER_LEAVE => {
+                let player = self.players.get_mut(name)?;
+                player.connected = false;
+                if let Some(session) = player.sessions.last_mut() {
+                    session.left_at.get_or_insert(timestamp);
+                }
+                player
+            },
+            _ => return None,
+        };
+
+        return Some(MatchUpdate::Player(player));
+    }
+
+    /// Summary of the match, as of `finished_at`.
+    pub fn result(&self, run: &RunInfo, finished_at: Timestamp) -> MatchResult {
+        MatchResult {
+            winning_team: self.winning_team.clone(),
+            started_at: self.started_at,
+            finished_at: finished_at,
+            duration: self.started_at.map(|started_at| finished_at.saturating_sub(started_at)),
+            map: run.map.clone(),
+            game_mod: run.game_mod.clone(),
+            creatures: run.creatures.clone(),
+            players: self.players.values().cloned().collect(),
+            teams: team_compositions(self.players.values()),
+            score: self.score.clone(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = MatchTracker::new(std::mem::take(&mut self.heroes));
+    }
+
+    fn player(&mut self, name: &str) -> &mut Player {
+        self.players.entry(name.to_owned()).or_insert_with(|| Player {
+            name: name.to_owned(),
+            hero: None,
+            hero_info: None,
+            team: None,
+            connected: true,
+            joined_at: None,
+            locked_at: None,
+            hero_history: Vec::new(),
+            sessions: Vec::new(),
+        })
+    }
+}
+
+/// Groups players by their team, players without a team are left out.
+pub fn team_compositions<'a>(players: impl IntoIterator<Item = &'a Player>) -> TeamCompositions {
+    let mut teams = TeamCompositions::new();
+    for player in players {
+        if let Some(team) = &player.team {
+            teams.entry(team.clone()).or_default().push(player.name.clone());
+        }
+    }
+
+    return teams;
+}
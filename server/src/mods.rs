@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use rgcp_common::config::LogRule;
 use serde::{Deserialize, Serialize};
 use std::{fs, path::PathBuf};
 
@@ -9,6 +10,7 @@ pub struct ModMetadata {
     pub creatures: Option<Vec<String>>,
     pub map: Option<String>,
     pub number_of_players: Option<usize>,
+    pub log_rules: Option<Vec<LogRule>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,35 @@
+use crate::api_keys::constant_time_eq;
+use hmac::Hmac;
+use rand::Rng;
+use sha2::Sha256;
+
+const HASH_ALGORITHM: &'static str = "pbkdf2-sha256";
+
+/// PBKDF2 iterations of new hashes, each hash stores its own so this can be raised without invalidating old ones.
+const ROUNDS: u32 = 600_000;
+
+/// Hashes the password of a web UI user, the result has the form `pbkdf2-sha256$<rounds>$<salt>$<hash>`.
+///
+/// Unlike API keys, which are long random strings, passwords are chosen by people and need a slow hash.
+pub fn hash_password(password: &str) -> String {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let salt = hex::encode(salt);
+    return format!("{}${}${}${}", HASH_ALGORITHM, ROUNDS, salt, hex::encode(derive(&salt, ROUNDS, password)));
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    return match parts.as_slice() {
+        [algorithm, rounds, salt, hash] if *algorithm == HASH_ALGORITHM => match (rounds.parse(), hex::decode(hash)) {
+            (Ok(rounds), Ok(hash)) if rounds > 0 => constant_time_eq(&hash, &derive(salt, rounds, password)),
+            _ => false,
+        },
+        _ => false,
+    };
+}
+
+fn derive(salt: &str, rounds: u32, password: &str) -> [u8; 32] {
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt.as_bytes(), rounds, &mut hash);
+    return hash;
+}
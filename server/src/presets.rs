@@ -0,0 +1,167 @@
+use crate::{error::ApiError, mods::get_mod};
+use anyhow::{bail, Result};
+use rgcp_common::{
+    config::{Config, Preset},
+    models::StartSpec,
+};
+use std::env::current_dir;
+
+/// Named start configurations, either defined in the config or created through the API.
+pub struct Presets {
+    configured: Vec<Preset>,
+    db: sled::Db,
+}
+
+impl Presets {
+    pub fn new(config: &Config) -> Self {
+        let configured = config
+            .presets
+            .iter()
+            .filter(|preset| match validate(config, &preset.spec) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::error!("Ignoring invalid preset {}: {}", preset.name, e);
+                    false
+                },
+            })
+            .cloned()
+            .collect();
+
+        let path = current_dir().unwrap().join(config.preset_path.get());
+        Presets { configured, db: sled::open(path).expect("Cannot open presets.") }
+    }
+
+    pub fn get_all(&self) -> Vec<Preset> {
+        let stored = self.db.iter().values().filter_map(|p| p.ok().and_then(|p| serde_cbor::from_slice(&p).ok()));
+        return self.configured.iter().cloned().chain(stored).collect();
+    }
+
+    pub fn get(&self, name: &str) -> Option<Preset> {
+        self.get_all().into_iter().find(|preset| preset.name == name)
+    }
+
+    pub fn add(&self, config: &Config, preset: Preset) -> Result<Preset> {
+        if preset.name.is_empty() {
+            bail!("preset name is required");
+        }
+        if self.configured.iter().any(|p| p.name == preset.name) {
+            bail!(ApiError::conflict(format!("preset {} is defined in the config and can't be replaced", preset.name)));
+        }
+        validate(config, &preset.spec)?;
+
+        self.db.insert(preset.name.as_bytes(), serde_cbor::to_vec(&preset)?)?;
+        self.db.flush()?;
+        return Ok(preset);
+    }
+
+    /// Removes a preset created through the API, presets from the config can't be removed.
+    pub fn remove(&self, name: &str) -> Option<Preset> {
+        let preset = self.db.remove(name.as_bytes()).ok().flatten().and_then(|p| serde_cbor::from_slice(&p).ok());
+        let _ = self.db.flush();
+        return preset;
+    }
+}
+
+/// Checks that a start specification only references known maps, creatures and mods, and that its overrides can't
+/// add lines to the game config.
+pub fn validate(config: &Config, spec: &StartSpec) -> Result<()> {
+    if !config.maps.iter().any(|map| map.id == spec.map) {
+        bail!("unknown map {}", spec.map);
+    }
+
+    if spec.creatures.len() != 3 {
+        bail!("exactly 3 creatures are required");
+    }
+
+    for creature in &spec.creatures {
+        if !config.creatures.iter().any(|c| c.id == *creature) {
+            bail!("unknown creature {}", creature);
+        }
+    }
+
+    if let Some(game_mod) = &spec.game_mod {
+        if get_mod(game_mod).is_err() {
+            bail!("unknown mod {}", game_mod);
+        }
+    }
+
+    for (key, value) in &spec.overrides {
+        if key.is_empty() || key.contains(|c| c == '=' || c == '\r' || c == '\n') {
+            bail!("invalid override key {:?}", key);
+        }
+        // The admin password is generated per instance and handed out by the API, it must not be replaced
+        if key.eq_ignore_ascii_case("AdminPassword") {
+            bail!("{} can't be overridden", key);
+        }
+        if let Some(allowed) = config.override_keys.get() {
+            if !allowed.contains(key) {
+                bail!("{} can't be overridden", key);
+            }
+        }
+        if value.contains(|c| c == '\r' || c == '\n') {
+            bail!("override {} contains a line break", key);
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(overrides: &[(&str, &str)]) -> StartSpec {
+        StartSpec {
+            map: "lv_canyon".to_owned(),
+            creatures: vec!["bloomer".to_owned(), "cerb".to_owned(), "cyclops".to_owned()],
+            max_players: 10,
+            game_mod: None,
+            overrides: overrides.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_overrides() {
+        let config = Config::default();
+        assert!(validate(&config, &spec(&[])).is_ok());
+        assert!(validate(&config, &spec(&[("TimeLimit", "30"), ("bAllowSpectators", "")])).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_overrides() {
+        let config = Config::default();
+        assert!(validate(&config, &spec(&[("", "1")])).is_err());
+        assert!(validate(&config, &spec(&[("Time=Limit", "1")])).is_err());
+        assert!(validate(&config, &spec(&[("TimeLimit", "30\nAdminPassword=x")])).is_err());
+        assert!(validate(&config, &spec(&[("TimeLimit\r", "30")])).is_err());
+    }
+
+    #[test]
+    fn rejects_admin_password() {
+        let config = Config::default();
+        assert!(validate(&config, &spec(&[("AdminPassword", "secret")])).is_err());
+        assert!(validate(&config, &spec(&[("adminpassword", "secret")])).is_err());
+
+        let config: Config = serde_json::from_str(r#"{ "override_keys": ["AdminPassword"] }"#).unwrap();
+        assert!(validate(&config, &spec(&[("AdminPassword", "secret")])).is_err());
+    }
+
+    #[test]
+    fn only_accepts_configured_keys() {
+        let config: Config = serde_json::from_str(r#"{ "override_keys": ["TimeLimit"] }"#).unwrap();
+        assert!(validate(&config, &spec(&[("TimeLimit", "30")])).is_ok());
+        assert!(validate(&config, &spec(&[("GoalScore", "5")])).is_err());
+
+        let config: Config = serde_json::from_str(r#"{ "override_keys": [] }"#).unwrap();
+        assert!(validate(&config, &spec(&[])).is_ok());
+        assert!(validate(&config, &spec(&[("TimeLimit", "30")])).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_content() {
+        let config = Config::default();
+        assert!(validate(&config, &StartSpec { map: "lv_unknown".to_owned(), ..spec(&[]) }).is_err());
+        assert!(validate(&config, &StartSpec { creatures: vec!["cerb".to_owned()], ..spec(&[]) }).is_err());
+        assert!(validate(&config, &StartSpec { game_mod: Some("unknown".to_owned()), ..spec(&[]) }).is_err());
+    }
+}
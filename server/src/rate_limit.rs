@@ -0,0 +1,162 @@
+use crate::{
+    error::{reject, ApiError},
+    server_manager::ServerManager,
+    session::User,
+};
+use rgcp_common::config::{Config, RateLimits};
+use rocket::{
+    request::{self, FromRequest, Request, State},
+    Outcome::Success,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const REQUEST_WINDOW: Duration = Duration::from_secs(60);
+const START_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Clients whose last hit is older than the window are forgotten once this many are tracked
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Sliding window counters for the limits configured in [`RateLimits`].
+pub struct RateLimiter {
+    limits: RateLimits,
+    requests: Mutex<HashMap<String, VecDeque<Instant>>>,
+    starts: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter { limits, requests: Mutex::new(HashMap::new()), starts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts a request to a mutating endpoint, returns how long to wait if the client is over its limit.
+    pub fn request(&self, ip: Option<String>, key: Option<String>) -> Result<(), Duration> {
+        let mut requests = self.requests.lock().unwrap();
+        let clients = [
+            (ip.map(|ip| format!("ip:{}", ip)), self.limits.requests_per_minute_per_ip),
+            (key.map(|key| format!("key:{}", key)), self.limits.requests_per_minute_per_key),
+        ];
+
+        for (client, limit) in clients.iter() {
+            if let (Some(client), Some(limit)) = (client, limit) {
+                check(&mut requests, client, *limit, REQUEST_WINDOW)?;
+            }
+        }
+
+        for (client, limit) in clients.iter() {
+            if let (Some(client), Some(_)) = (client, limit) {
+                record(&mut requests, client, REQUEST_WINDOW);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Checks the start quotas of a client which currently has `instances` running or queued.
+    pub fn check_start(&self, client: &str, instances: usize) -> Result<(), ApiError> {
+        if let Some(max) = self.limits.max_concurrent_instances {
+            if instances >= max {
+                let message = format!("at most {} instances may be used at the same time", max);
+                return Err(ApiError::too_many_requests(message, None));
+            }
+        }
+
+        if let Some(max) = self.limits.max_starts_per_hour {
+            let mut starts = self.starts.lock().unwrap();
+            check(&mut starts, client, max, START_WINDOW).map_err(|retry_after| {
+                ApiError::too_many_requests(format!("at most {} starts per hour", max), Some(retry_after))
+            })?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn record_start(&self, client: &str) {
+        if self.limits.max_starts_per_hour.is_some() {
+            record(&mut self.starts.lock().unwrap(), client, START_WINDOW);
+        }
+    }
+}
+
+fn check(
+    clients: &mut HashMap<String, VecDeque<Instant>>,
+    client: &str,
+    limit: usize,
+    window: Duration,
+) -> Result<(), Duration> {
+    let now = Instant::now();
+    let hits = clients.entry(client.to_owned()).or_default();
+    while hits.front().map(|hit| now.duration_since(*hit) >= window).unwrap_or(false) {
+        hits.pop_front();
+    }
+
+    if hits.len() < limit {
+        return Ok(());
+    }
+
+    return Err(hits.front().map(|oldest| window - now.duration_since(*oldest)).unwrap_or(window));
+}
+
+fn record(clients: &mut HashMap<String, VecDeque<Instant>>, client: &str, window: Duration) {
+    let now = Instant::now();
+    if clients.len() >= MAX_TRACKED_CLIENTS {
+        clients.retain(|_, hits| hits.back().map(|hit| now.duration_since(*hit) < window).unwrap_or(false));
+    }
+
+    clients.entry(client.to_owned()).or_default().push_back(now);
+}
+
+/// Address of the client, the `X-Real-IP` header is only trusted on requests from one of the `trusted_proxies`.
+pub fn client_ip(request: &Request) -> Option<IpAddr> {
+    let remote = request.remote()?.ip();
+    let trusted = match request.guard::<State<Config>>() {
+        Success(config) => config.trusted_proxies.contains(&remote),
+        _ => false,
+    };
+
+    return if trusted { request.real_ip().or(Some(remote)) } else { Some(remote) };
+}
+
+/// Identifies who is starting a game for the start quotas.
+pub fn client_id(api_key: Option<&str>, user: Option<&User>, request_ip: Option<String>) -> String {
+    match (api_key, user) {
+        (Some(name), _) => format!("key:{}", name),
+        (None, Some(user)) => format!("user:{}", user.0),
+        (None, None) => format!("ip:{}", request_ip.unwrap_or_default()),
+    }
+}
+
+/// Request guard for mutating endpoints, enforces the per-IP and per-key request limits.
+pub struct RateLimit {
+    pub client_ip: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RateLimit {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let server_manager = match request.guard::<State<Arc<ServerManager>>>() {
+            Success(server_manager) => server_manager,
+            _ => return reject(request, ApiError::internal("server manager unavailable"), ()),
+        };
+
+        let client_ip = client_ip(request).map(|ip| ip.to_string());
+        let key = request
+            .headers()
+            .get_one("x-api-key")
+            .and_then(|key| server_manager.api_keys().verify(key))
+            .map(|api_key| api_key.name);
+
+        return match server_manager.rate_limiter().request(client_ip.clone(), key) {
+            Ok(_) => Success(RateLimit { client_ip }),
+            Err(retry_after) => {
+                let error = ApiError::too_many_requests("too many requests", Some(retry_after));
+                reject(request, error, ())
+            },
+        };
+    }
+}
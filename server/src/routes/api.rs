@@ -1,33 +1,86 @@
-use crate::{api_key::ApiKey, server_manager::ServerManager, AppInfo};
-use rgcp_common::{config::Config, models::*};
-use rocket::{get, post, request::State};
+use crate::{
+    api_key::{AdminKey, ApiKey, InstanceAccess, ReadAccess, StartKey},
+    audit::{Actor, AuditQuery},
+    error::ApiError,
+    match_history::MatchQuery,
+    presets::validate,
+    rate_limit::{client_id, RateLimit},
+    server_manager::{ServerManager, StartOutcome},
+    AppInfo,
+};
+use rgcp_common::{
+    config::{ApiScope, Config, Preset},
+    models::*,
+};
+use rocket::{delete, get, post, request::State, response::NamedFile};
 use rocket_contrib::json::Json;
+use serde_json::json;
 use std::sync::Arc;
 
 #[post("/api/version")]
-pub fn version(_api_key: ApiKey) -> Json<VersionResponse> {
+pub fn version(_access: ReadAccess) -> Json<VersionResponse> {
+    return version_response(API_VERSION);
+}
+
+/// Version info as reported by the version route of API `api_version`.
+pub fn version_response(api_version: u32) -> Json<VersionResponse> {
     return Json(VersionResponse {
         app_version: AppInfo::get().version_string.clone(),
         app_version_major: AppInfo::get().pkg_version_major.clone(),
         app_version_minor: AppInfo::get().pkg_version_minor.clone(),
         app_version_patch: AppInfo::get().pkg_version_patch.clone(),
-        api_version: API_VERSION,
+        api_version: api_version,
+        api_versions: API_VERSIONS.to_vec(),
     });
 }
 
-#[get("/api/logs?<id>&<from_line>&<to_line>")]
+#[get("/api/logs?<id>&<run>&<from_line>&<to_line>")]
 pub fn logs(
     server_manager: State<Arc<ServerManager>>,
-    id: InstanceID,
+    id: Option<InstanceID>,
+    run: Option<String>,
     from_line: Option<u64>,
     to_line: Option<u64>,
-) -> Json<Vec<String>> {
-    return Json(server_manager.get_logs(id, from_line.unwrap_or(0), to_line.unwrap_or(std::u64::MAX)));
+    _access: ReadAccess,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let from_line = from_line.unwrap_or(0);
+    let to_line = to_line.unwrap_or(std::u64::MAX);
+
+    if let Some(run) = run {
+        let lines = server_manager.archive().get_run_log_lines(&run, from_line, to_line);
+        return lines.map(Json).ok_or_else(|| ApiError::not_found(format!("unknown run {}", run)));
+    }
+
+    return match id {
+        Some(id) => Ok(Json(server_manager.get_logs(known_instance(&server_manager, id)?, from_line, to_line))),
+        None => Err(ApiError::bad_request("id or run is required")),
+    };
 }
 
-#[get("/api/players?<id>")]
-pub fn get_players(server_manager: State<Arc<ServerManager>>, id: InstanceID) -> Json<Vec<Player>> {
-    return Json(server_manager.get_players(id));
+#[get("/api/players?<id>&<connected>")]
+pub fn get_players(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    connected: Option<bool>,
+    _access: ReadAccess,
+) -> Result<Json<Vec<Player>>, ApiError> {
+    return Ok(Json(
+        server_manager
+            .get_players(known_instance(&server_manager, id)?)
+            .into_iter()
+            .filter(|player| connected.map(|connected| player.connected == connected).unwrap_or(true))
+            .collect(),
+    ));
+}
+
+#[get("/api/instance?<id>")]
+pub fn instance(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    _access: ReadAccess,
+) -> Result<Json<InstanceResponse>, ApiError> {
+    let instance = server_manager.get_instance(known_instance(&server_manager, id)?);
+    return instance.map(Json).ok_or_else(|| not_running(id));
 }
 
 #[post("/api/start", data = "<data>")]
@@ -35,50 +88,424 @@ pub fn start(
     server_manager: State<Arc<ServerManager>>,
     config: State<Config>,
     data: Json<StartRequest>,
-    _api_key: ApiKey,
-) -> Json<StartResponse> {
-    let creatures = [
-        data.creature0.as_ref().unwrap_or(&config.default_creatures.get()[0]).to_owned(),
-        data.creature1.as_ref().unwrap_or(&config.default_creatures.get()[1]).to_owned(),
-        data.creature2.as_ref().unwrap_or(&config.default_creatures.get()[2]).to_owned(),
-    ];
-
-    if let Some(instance_id) = server_manager.start_new_instance(&data.map, &creatures, data.max_players.unwrap_or(10), &data.game_mod)
-    {
-        return Json(StartResponse {
-            error: None,
-            open_url: Some(format!("{}:{}", *config.server_url, *config.server_port + instance_id as u16)),
-        });
-    }
+    rate_limit: RateLimit,
+    api_key: StartKey,
+    actor: Actor,
+) -> Result<Json<StartResponse>, ApiError> {
+    let response = StartResponse { open_url: None, ticket: None, instance_id: None, owner_token: None };
+    let spec = match start_spec(&server_manager, &config, &data) {
+        Ok(spec) => spec,
+        Err(e) => {
+            server_manager.audit().record(AuditAction::Start, &actor, None, &*data, Some(e.to_string()));
+            return Err(e);
+        },
+    };
+
+    let started_by = client_id(Some(api_key.0.name()), None, rate_limit.client_ip);
+    let outcome = server_manager.start_or_enqueue(spec.clone(), &started_by)?;
+    server_manager.audit_start(&actor, &spec, &outcome);
+
+    return match outcome {
+        StartOutcome::Started(instance_id) => Ok(Json(StartResponse {
+            open_url: Some(server_manager.open_url(instance_id)),
+            instance_id: Some(instance_id),
+            owner_token: server_manager.owner_token(instance_id),
+            ..response
+        })),
+        StartOutcome::Queued(ticket) => Ok(Json(StartResponse { ticket: Some(ticket), ..response })),
+        StartOutcome::Unavailable => Err(ApiError::unavailable("no instances available")),
+    };
+}
+
+#[get("/api/schedules")]
+pub fn schedules(server_manager: State<Arc<ServerManager>>, _access: ReadAccess) -> Json<Vec<ScheduledStart>> {
+    return Json(server_manager.scheduler().get_all());
+}
+
+#[post("/api/schedules", data = "<data>")]
+pub fn add_schedule(
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    data: Json<ScheduleRequest>,
+    _rate_limit: RateLimit,
+    _api_key: StartKey,
+    actor: Actor,
+) -> Result<Json<ScheduleResponse>, ApiError> {
+    let data = data.into_inner();
+    let result = start_spec(&server_manager, &config, &data.start).and_then(|spec| {
+        let reserve_minutes = data.reserve_minutes.unwrap_or(15);
+        let (name, start_at, cron) = (data.name.clone(), data.start_at, data.cron.clone());
+        Ok(server_manager.scheduler().add(name, spec, start_at, cron, reserve_minutes)?)
+    });
+
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::AddSchedule, &actor, None, &data, error);
+    return result.map(|schedule| Json(ScheduleResponse { schedule: Some(schedule) }));
+}
+
+#[delete("/api/schedules/<id>")]
+pub fn remove_schedule(
+    server_manager: State<Arc<ServerManager>>,
+    id: String,
+    _rate_limit: RateLimit,
+    _api_key: StartKey,
+    actor: Actor,
+) -> Result<Json<ScheduledStart>, ApiError> {
+    let result = server_manager.scheduler().remove(&id).ok_or_else(|| ApiError::not_found("unknown schedule"));
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::RemoveSchedule, &actor, None, json!({ "id": id }), error);
+    return result.map(Json);
+}
+
+#[get("/api/presets")]
+pub fn presets(server_manager: State<Arc<ServerManager>>, _access: ReadAccess) -> Json<Vec<Preset>> {
+    return Json(server_manager.presets().get_all());
+}
+
+#[post("/api/presets", data = "<data>")]
+pub fn add_preset(
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    data: Json<Preset>,
+    _rate_limit: RateLimit,
+    _api_key: AdminKey,
+    actor: Actor,
+) -> Result<Json<PresetResponse>, ApiError> {
+    let result = server_manager.presets().add(&config, data.clone()).map_err(ApiError::from);
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::AddPreset, &actor, None, &*data, error);
+    return result.map(|preset| Json(PresetResponse { preset: Some(preset) }));
+}
+
+#[delete("/api/presets/<name>")]
+pub fn remove_preset(
+    server_manager: State<Arc<ServerManager>>,
+    name: String,
+    _rate_limit: RateLimit,
+    _api_key: AdminKey,
+    actor: Actor,
+) -> Result<Json<Preset>, ApiError> {
+    let result = server_manager.presets().remove(&name).ok_or_else(|| ApiError::not_found("unknown preset"));
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::RemovePreset, &actor, None, json!({ "name": name }), error);
+    return result.map(Json);
+}
+
+#[get("/api/keys")]
+pub fn api_keys(server_manager: State<Arc<ServerManager>>, _api_key: AdminKey) -> Json<Vec<ApiKeyInfo>> {
+    return Json(server_manager.api_keys().get_all());
+}
+
+#[post("/api/keys", data = "<data>")]
+pub fn create_api_key(
+    server_manager: State<Arc<ServerManager>>,
+    data: Json<ApiKeyRequest>,
+    _rate_limit: RateLimit,
+    _api_key: AdminKey,
+    actor: Actor,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let result = server_manager.api_keys().create(&data.name, data.scopes.clone()).map_err(ApiError::from);
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::CreateApiKey, &actor, None, &*data, error);
+    return result.map(api_key_response);
+}
+
+#[post("/api/keys/<name>/rotate")]
+pub fn rotate_api_key(
+    server_manager: State<Arc<ServerManager>>,
+    name: String,
+    _rate_limit: RateLimit,
+    _api_key: AdminKey,
+    actor: Actor,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let result = server_manager.api_keys().rotate(&name).map_err(ApiError::from);
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::RotateApiKey, &actor, None, json!({ "name": name }), error);
+    return result.map(api_key_response);
+}
+
+#[delete("/api/keys/<name>")]
+pub fn revoke_api_key(
+    server_manager: State<Arc<ServerManager>>,
+    name: String,
+    _rate_limit: RateLimit,
+    _api_key: AdminKey,
+    actor: Actor,
+) -> Result<Json<ApiKeyInfo>, ApiError> {
+    let result = server_manager.api_keys().revoke(&name);
+    let result = result.ok_or_else(|| ApiError::not_found(format!("unknown key {}", name)));
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::RevokeApiKey, &actor, None, json!({ "name": name }), error);
+    return result.map(Json);
+}
+
+#[get("/api/audit?<from>&<to>&<action>&<actor>&<page>&<per_page>")]
+pub fn audit(
+    server_manager: State<Arc<ServerManager>>,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    action: Option<String>,
+    actor: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    _api_key: AdminKey,
+) -> Result<Json<AuditResponse>, ApiError> {
+    let action = match action {
+        Some(action) => Some(
+            serde_json::from_value(action.clone().into())
+                .map_err(|_| ApiError::invalid(format!("unknown action {}", action)))?,
+        ),
+        None => None,
+    };
+
+    let page = page.unwrap_or(0);
+    let per_page = per_page.unwrap_or(25).min(100).max(1);
+    let query = AuditQuery { from, to, action, actor };
+    let (total, entries) = server_manager.audit().query(&query, page, per_page);
+    return Ok(Json(AuditResponse { total, page, per_page, entries }));
+}
 
-    return Json(StartResponse { error: Some("no instances available".to_owned()), open_url: None });
+#[get("/api/queue")]
+pub fn queue(server_manager: State<Arc<ServerManager>>, _access: ReadAccess) -> Json<Vec<QueuedStart>> {
+    let queue = server_manager
+        .get_queue()
+        .into_iter()
+        .map(|ticket| QueuedStart { position: ticket.position, spec: ticket.spec, created_at: ticket.created_at })
+        .collect();
+    return Json(queue);
+}
+
+#[get("/api/queue/<ticket>")]
+pub fn queue_ticket(server_manager: State<Arc<ServerManager>>, ticket: String) -> Result<Json<QueueTicket>, ApiError> {
+    return server_manager.get_ticket(&ticket).map(Json).ok_or_else(|| ApiError::not_found("unknown ticket"));
+}
+
+/// Hands the owner token of a started request to the key which queued it, the token is only handed out once.
+#[post("/api/queue/<ticket>/owner_token")]
+pub fn queue_ticket_owner_token(
+    server_manager: State<Arc<ServerManager>>,
+    ticket: String,
+    rate_limit: RateLimit,
+    api_key: StartKey,
+) -> Result<Json<StartResponse>, ApiError> {
+    let started_by = client_id(Some(api_key.0.name()), None, rate_limit.client_ip);
+    let (instance_id, owner_token) = server_manager
+        .take_owner_token(&ticket, Some(&started_by))
+        .ok_or_else(|| ApiError::not_found("no owner token for this ticket"))?;
+
+    return Ok(Json(StartResponse {
+        open_url: Some(server_manager.open_url(instance_id)),
+        ticket: None,
+        instance_id: Some(instance_id),
+        owner_token: Some(owner_token),
+    }));
+}
+
+/// Cancels a waiting request, only the key which queued it or one with the kill scope may do so.
+#[delete("/api/queue/<ticket>")]
+pub fn cancel_queue_ticket(
+    server_manager: State<Arc<ServerManager>>,
+    ticket: String,
+    rate_limit: RateLimit,
+    api_key: ApiKey,
+) -> Result<Json<QueueTicket>, ApiError> {
+    let started_by = client_id(Some(api_key.name()), None, rate_limit.client_ip);
+    let started_by = Some(started_by.as_str()).filter(|_| !api_key.has_scope(ApiScope::Kill));
+    return server_manager
+        .cancel_ticket(&ticket, started_by)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("unknown ticket"));
 }
 
 #[post("/api/kill", data = "<data>")]
 pub fn kill(
     server_manager: State<Arc<ServerManager>>,
     data: Json<KillRequest>,
-    _api_key: ApiKey,
-) -> Json<KillResponse> {
-    return Json(KillResponse { error: server_manager.kill_instance(data.id) });
+    _rate_limit: RateLimit,
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<Json<KillResponse>, ApiError> {
+    access.check(&server_manager, data.id, ApiScope::Kill)?;
+
+    let result = server_manager.kill_instance(data.id);
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::Kill, &actor, Some(data.id), (), error);
+    return result.map(|_| Json(KillResponse {}));
+}
+
+#[post("/api/restart", data = "<data>")]
+pub fn restart(
+    server_manager: State<Arc<ServerManager>>,
+    data: Json<RestartRequest>,
+    _rate_limit: RateLimit,
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<Json<RestartResponse>, ApiError> {
+    access.check(&server_manager, data.id, ApiScope::Kill)?;
+
+    let result = server_manager.restart_instance(data.id);
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::Restart, &actor, Some(data.id), (), error);
+    return result.map(|_| Json(RestartResponse {}));
 }
 
 #[post("/api/admin_pw", data = "<data>")]
 pub fn admin_pw(
     server_manager: State<Arc<ServerManager>>,
     data: Json<AdminPWRequest>,
-    _api_key: ApiKey,
-) -> Json<AdminPWResponse> {
-    return Json(AdminPWResponse { admin_pw: server_manager.get_admin_pw(data.id) });
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<Json<AdminPWResponse>, ApiError> {
+    access.check(&server_manager, data.id, ApiScope::AdminPw)?;
+
+    let result = server_manager.get_admin_pw(data.id).ok_or_else(|| not_running(data.id));
+    let error = result.as_ref().err().map(ToString::to_string);
+    server_manager.audit().record(AuditAction::AdminPw, &actor, Some(data.id), (), error);
+    return result.map(|admin_pw| Json(AdminPWResponse { admin_pw: Some(admin_pw) }));
 }
 
 #[post("/api/events", data = "<data>")]
 pub fn events(
     server_manager: State<Arc<ServerManager>>,
     data: Json<EventsRequest>,
-    _api_key: ApiKey,
+    _access: ReadAccess,
 ) -> Json<EventsResponse> {
     let events = server_manager.get_events(data.timestamp);
     let timestamp = events.last().map(|e| e.timestamp).unwrap_or(data.timestamp);
     return Json(EventsResponse { events: events, timestamp: timestamp });
 }
+
+#[get("/api/matches?<from>&<to>&<map>&<player>&<page>&<per_page>")]
+pub fn matches(
+    server_manager: State<Arc<ServerManager>>,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    map: Option<String>,
+    player: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    _access: ReadAccess,
+) -> Json<MatchesResponse> {
+    let page = page.unwrap_or(0);
+    let per_page = per_page.unwrap_or(25).min(100).max(1);
+    let query = MatchQuery { from, to, map, player };
+    let (total, matches) = server_manager.history().query(&query, page, per_page);
+    return Json(MatchesResponse { total, page, per_page, matches });
+}
+
+#[get("/api/matches/<run_id>")]
+pub fn match_record(
+    server_manager: State<Arc<ServerManager>>,
+    run_id: String,
+    _access: ReadAccess,
+) -> Option<Json<MatchRecord>> {
+    return server_manager.history().get(&run_id).map(Json);
+}
+
+#[get("/api/players/<name>/stats")]
+pub fn player_stats(
+    server_manager: State<Arc<ServerManager>>,
+    name: String,
+    _access: ReadAccess,
+) -> Option<Json<PlayerStats>> {
+    return server_manager.history().player_stats(&name).map(Json);
+}
+
+#[get("/api/leaderboard?<limit>")]
+pub fn leaderboard(
+    server_manager: State<Arc<ServerManager>>,
+    limit: Option<usize>,
+    _access: ReadAccess,
+) -> Json<Vec<PlayerStats>> {
+    return Json(server_manager.history().leaderboard().into_iter().take(limit.unwrap_or(50)).collect());
+}
+
+#[get("/api/runs")]
+pub fn runs(server_manager: State<Arc<ServerManager>>, _access: ReadAccess) -> Json<Vec<RunInfo>> {
+    return Json(server_manager.archive().get_runs());
+}
+
+#[get("/api/runs/<run_id>")]
+pub fn run(
+    server_manager: State<Arc<ServerManager>>,
+    run_id: String,
+    _access: ReadAccess,
+) -> Option<Json<RunResponse>> {
+    let archive = server_manager.archive();
+    return archive.get_run(&run_id).map(|run| {
+        Json(RunResponse {
+            run: run,
+            events: archive.get_run_events(&run_id),
+            players: archive.get_run_players(&run_id),
+            result: archive.get_run_result(&run_id),
+            files: archive.get_run_files(&run_id),
+        })
+    });
+}
+
+#[get("/api/runs/<run_id>/<file>")]
+pub fn run_file(
+    server_manager: State<Arc<ServerManager>>,
+    run_id: String,
+    file: String,
+    _access: ReadAccess,
+) -> Option<NamedFile> {
+    return server_manager.archive().run_file(&run_id, &file).and_then(|file| NamedFile::open(file).ok());
+}
+
+fn start_spec(server_manager: &ServerManager, config: &Config, data: &StartRequest) -> Result<StartSpec, ApiError> {
+    let preset = match &data.preset {
+        Some(name) => {
+            let preset = server_manager.presets().get(name);
+            Some(preset.ok_or_else(|| ApiError::invalid(format!("unknown preset {}", name)))?.spec)
+        },
+        None => None,
+    };
+
+    let creatures = [&data.creature0, &data.creature1, &data.creature2]
+        .iter()
+        .enumerate()
+        .map(|(i, creature)| {
+            creature
+                .as_ref()
+                .cloned()
+                .or_else(|| preset.as_ref().and_then(|preset| preset.creatures.get(i).cloned()))
+                .unwrap_or_else(|| config.default_creatures.get()[i].to_owned())
+        })
+        .collect();
+
+    let mut overrides = preset.as_ref().map(|preset| preset.overrides.clone()).unwrap_or_default();
+    overrides.extend(data.overrides.clone());
+
+    let spec = StartSpec {
+        map: data
+            .map
+            .clone()
+            .or_else(|| preset.as_ref().map(|preset| preset.map.clone()))
+            .ok_or_else(|| ApiError::invalid("map or preset is required"))?,
+        creatures: creatures,
+        max_players: data.max_players.or(preset.as_ref().map(|preset| preset.max_players)).unwrap_or(10),
+        game_mod: data.game_mod.clone().or_else(|| preset.as_ref().and_then(|preset| preset.game_mod.clone())),
+        overrides: overrides,
+    };
+
+    validate(config, &spec)?;
+    return Ok(spec);
+}
+
+fn api_key_response((key, info): (String, ApiKeyInfo)) -> Json<ApiKeyResponse> {
+    return Json(ApiKeyResponse { key: Some(key), info: Some(info) });
+}
+
+/// Rejects ids outside of the configured instances.
+fn known_instance(server_manager: &ServerManager, id: InstanceID) -> Result<InstanceID, ApiError> {
+    if !server_manager.is_instance(id) {
+        return Err(ApiError::not_found(format!("unknown instance {}", id)));
+    }
+
+    return Ok(id);
+}
+
+fn not_running(id: InstanceID) -> ApiError {
+    ApiError::not_found(format!("instance {} isn't running", id))
+}
@@ -0,0 +1,172 @@
+use crate::{
+    api_key::{InstanceAccess, ReadAccess, StartKey, StreamAccess},
+    audit::Actor,
+    error::ApiError,
+    event_stream::{EventStream, LastEventId, Source},
+    rate_limit::RateLimit,
+    routes::api,
+    server_manager::ServerManager,
+};
+use rgcp_common::{config::Config, models::*};
+use rocket::{
+    delete,
+    get,
+    post,
+    request::State,
+    response::status::{Accepted, Created, NoContent},
+    Responder,
+};
+use rocket_contrib::json::Json;
+use std::sync::Arc;
+
+/// `201 Created` with the new instance as `Location`, or `202 Accepted` when the request was queued.
+#[derive(Responder)]
+pub enum StartedResponse {
+    Started(Created<Json<StartResponse>>),
+    Queued(Accepted<Json<StartResponse>>),
+}
+
+#[get("/api/v3/version")]
+pub fn version(_access: ReadAccess) -> Json<VersionResponse> {
+    return api::version_response(3);
+}
+
+#[get("/api/v3/instances")]
+pub fn instances(server_manager: State<Arc<ServerManager>>, _access: ReadAccess) -> Json<Vec<InstanceResponse>> {
+    return Json(server_manager.get_instances());
+}
+
+#[post("/api/v3/instances", data = "<data>")]
+pub fn start(
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    data: Json<StartRequest>,
+    rate_limit: RateLimit,
+    api_key: StartKey,
+    actor: Actor,
+) -> Result<StartedResponse, ApiError> {
+    let response = api::start(server_manager, config, data, rate_limit, api_key, actor)?.into_inner();
+    return Ok(match response.instance_id {
+        Some(id) => StartedResponse::Started(Created(format!("/api/v3/instances/{}", id), Some(Json(response)))),
+        None => StartedResponse::Queued(Accepted(Some(Json(response)))),
+    });
+}
+
+#[get("/api/v3/instances/<id>")]
+pub fn instance(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    access: ReadAccess,
+) -> Result<Json<InstanceResponse>, ApiError> {
+    return api::instance(server_manager, id, access);
+}
+
+#[delete("/api/v3/instances/<id>")]
+pub fn kill(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    rate_limit: RateLimit,
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<NoContent, ApiError> {
+    api::kill(server_manager, Json(KillRequest { id }), rate_limit, access, actor)?;
+    return Ok(NoContent);
+}
+
+#[post("/api/v3/instances/<id>/restart")]
+pub fn restart(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    rate_limit: RateLimit,
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<Accepted<()>, ApiError> {
+    api::restart(server_manager, Json(RestartRequest { id }), rate_limit, access, actor)?;
+    return Ok(Accepted(None));
+}
+
+#[get("/api/v3/instances/<id>/admin_pw")]
+pub fn admin_pw(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    access: InstanceAccess,
+    actor: Actor,
+) -> Result<Json<AdminPWResponse>, ApiError> {
+    return api::admin_pw(server_manager, Json(AdminPWRequest { id }), access, actor);
+}
+
+#[get("/api/v3/instances/<id>/logs?<from_line>&<to_line>")]
+pub fn logs(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    from_line: Option<u64>,
+    to_line: Option<u64>,
+    access: ReadAccess,
+) -> Result<Json<Vec<String>>, ApiError> {
+    return api::logs(server_manager, Some(id), None, from_line, to_line, access);
+}
+
+#[get("/api/v3/instances/<id>/players?<connected>")]
+pub fn players(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    connected: Option<bool>,
+    access: ReadAccess,
+) -> Result<Json<Vec<Player>>, ApiError> {
+    return api::get_players(server_manager, id, connected, access);
+}
+
+/// Events after the `since` timestamp, pass the returned timestamp as `since` to get only new ones.
+#[get("/api/v3/instances/<id>/events?<since>")]
+pub fn events(
+    server_manager: State<Arc<ServerManager>>,
+    id: InstanceID,
+    since: Option<Timestamp>,
+    _access: ReadAccess,
+) -> Result<Json<EventsResponse>, ApiError> {
+    if !server_manager.is_instance(id) {
+        return Err(ApiError::not_found(format!("unknown instance {}", id)));
+    }
+
+    let since = since.unwrap_or(0);
+    let events = server_manager.get_instance_events(id, since);
+    let timestamp = events.last().map(|e| e.timestamp).unwrap_or(since);
+    return Ok(Json(EventsResponse { events, timestamp }));
+}
+
+/// Server-sent `event`, `log` and `instance` messages as they happen, and `shutdown` once the instance stopped. Each
+/// kind is only sent if the `access_policy` of its counterpart (`/api/events`, `/api/logs`, `/api/instance` and
+/// `/api/players`) allows it.
+#[get("/api/v3/instances/<id>/stream?<since>&<from_line>")]
+pub fn instance_stream(
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    id: InstanceID,
+    since: Option<Timestamp>,
+    from_line: Option<u64>,
+    last_event_id: LastEventId,
+    access: StreamAccess,
+) -> Result<EventStream, ApiError> {
+    if !server_manager.is_instance(id) {
+        return Err(ApiError::not_found(format!("unknown instance {}", id)));
+    }
+
+    let server_manager = server_manager.inner().clone();
+    let max_streams = *config.max_event_streams.get();
+    let source = Source::Instance(id, access);
+    return EventStream::open(server_manager, source, last_event_id, since, from_line, max_streams);
+}
+
+/// Server-sent events of all instances, the stream counterpart of `/api/events`.
+#[get("/api/v3/events/stream?<since>")]
+pub fn event_stream(
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    since: Option<Timestamp>,
+    last_event_id: LastEventId,
+    _access: ReadAccess,
+) -> Result<EventStream, ApiError> {
+    let server_manager = server_manager.inner().clone();
+    let max_streams = *config.max_event_streams.get();
+    return EventStream::open(server_manager, Source::Events, last_event_id, since, None, max_streams);
+}
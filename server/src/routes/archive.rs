@@ -0,0 +1,57 @@
+use crate::{server_manager::ServerManager, session::ViewAccess, templates::render};
+use rgcp_common::{config::Config, models::*, AppInfo};
+use rocket::{get, request::State, response::content::Html};
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct RunsContext {
+    title: String,
+    app_version: String,
+    runs: Vec<RunInfo>,
+}
+
+#[derive(Serialize)]
+struct RunContext {
+    title: String,
+    app_version: String,
+    run: RunInfo,
+    events: Vec<Event>,
+    players: Vec<Player>,
+    result: Option<MatchResult>,
+    log: String,
+}
+
+#[get("/runs")]
+pub fn runs(config: State<Config>, server_manager: State<Arc<ServerManager>>, _access: ViewAccess) -> Html<String> {
+    let context = RunsContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        runs: server_manager.archive().get_runs(),
+    };
+
+    let html = render("runs", context);
+    Html(html)
+}
+
+#[get("/run?<id>")]
+pub fn run(
+    id: String,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _access: ViewAccess,
+) -> Option<Html<String>> {
+    let archive = server_manager.archive();
+    let context = RunContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        run: archive.get_run(&id)?,
+        events: archive.get_run_events(&id),
+        players: archive.get_run_players(&id),
+        result: archive.get_run_result(&id),
+        log: archive.get_run_log(&id).unwrap_or_default(),
+    };
+
+    let html = render("run", context);
+    Some(Html(html))
+}
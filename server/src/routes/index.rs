@@ -1,20 +1,28 @@
 use crate::{
+    audit::Actor,
     mods::{get_mods, Mod},
-    server_manager::ServerManager,
-    templates::TERA,
+    error::ApiError,
+    presets::validate,
+    rate_limit::{client_id, RateLimit},
+    routes::instance::{owner_cookie, TICKET_COOKIE},
+    server_manager::{ServerManager, StartOutcome},
+    session::{StartAccess, ViewAccess},
+    templates::render,
 };
 use rgcp_common::{
-    config::{Config, Creature, Map},
+    config::{Config, Creature, Map, Preset, WebLogin},
+    models::{QueueTicket, ScheduledStart, StartSpec},
     AppInfo,
 };
 use rocket::{
     get,
+    http::{Cookie, Cookies},
     post,
     request::{LenientForm, FromForm, State},
     response::{content::Html, Redirect},
 };
 use serde::Serialize;
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 #[derive(Serialize)]
 struct IndexContext {
@@ -26,10 +34,44 @@ struct IndexContext {
     maps: Vec<Map>,
     default_creatures: Vec<String>,
     mods: Vec<Mod>,
+    presets: Vec<Preset>,
+    queue: Vec<QueueEntry>,
+    queue_enabled: bool,
+    schedules: Vec<ScheduledStart>,
+    ticket: Option<QueueTicket>,
+    user: Option<String>,
+    login_enabled: bool,
+    can_start: bool,
 }
 
-#[get("/")]
-pub fn get(config: State<Config>, server_manager: State<Arc<ServerManager>>) -> Html<String> {
+/// A waiting start request, without the ticket id so it can be shown to everyone
+#[derive(Serialize)]
+struct QueueEntry {
+    position: Option<usize>,
+    map: String,
+    game_mod: Option<String>,
+    mine: bool,
+}
+
+#[get("/?<ticket>")]
+pub fn get(
+    ticket: Option<String>,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    access: ViewAccess,
+) -> Html<String> {
+    let user = access.0.map(|user| user.0);
+    let queue = server_manager
+        .get_queue()
+        .into_iter()
+        .map(|entry| QueueEntry {
+            position: entry.position,
+            mine: Some(&entry.ticket) == ticket.as_ref(),
+            map: entry.spec.map,
+            game_mod: entry.spec.game_mod,
+        })
+        .collect();
+
     let context = IndexContext {
         title: config.title.get().to_owned(),
         running_instances: server_manager.running_instances(),
@@ -39,9 +81,17 @@ pub fn get(config: State<Config>, server_manager: State<Arc<ServerManager>>) ->
         maps: config.maps.get().clone(),
         default_creatures: config.default_creatures.get().clone(),
         mods: get_mods(),
+        presets: server_manager.presets().get_all(),
+        queue: queue,
+        queue_enabled: *config.start_queue_size > 0,
+        schedules: server_manager.scheduler().get_all(),
+        ticket: ticket.and_then(|ticket| server_manager.get_ticket(&ticket)),
+        login_enabled: *config.web_login != WebLogin::Disabled,
+        can_start: user.is_some() || *config.web_login < WebLogin::Start,
+        user: user,
     };
 
-    let html = TERA.render("index", &tera::Context::from_serialize(context).unwrap()).unwrap();
+    let html = render("index", context);
     Html(html)
 }
 
@@ -54,20 +104,73 @@ pub struct StartForm {
     creature1: String,
     creature2: String,
     game_mod: String,
+    preset: Option<String>,
 }
 
 #[post("/start", data = "<form>")]
-pub fn start(form: LenientForm<StartForm>, server_manager: State<Arc<ServerManager>>) -> Redirect {
-    if let Some(id) = server_manager.start_new_instance(
-        &form.map,
-        &[form.creature0.clone(), form.creature1.clone(), form.creature2.clone()],
-        form.max_players,
-        &match form.game_mod.as_str() {
+pub fn start(
+    form: LenientForm<StartForm>,
+    server_manager: State<Arc<ServerManager>>,
+    config: State<Config>,
+    rate_limit: RateLimit,
+    access: StartAccess,
+    actor: Actor,
+    // Guards reading the session cookie must run first, they only get an empty jar while `Cookies` is held
+    mut cookies: Cookies,
+) -> Result<Redirect, ApiError> {
+    let preset = match form.preset.as_ref().filter(|name| !name.is_empty()) {
+        Some(name) => {
+            let preset = server_manager.presets().get(name);
+            Some(preset.ok_or_else(|| ApiError::invalid(format!("unknown preset {}", name)))?)
+        },
+        None => None,
+    };
+    let spec = preset.map(|preset| preset.spec).unwrap_or_else(|| StartSpec {
+        map: form.map.clone(),
+        creatures: vec![form.creature0.clone(), form.creature1.clone(), form.creature2.clone()],
+        max_players: form.max_players,
+        game_mod: match form.game_mod.as_str() {
             "" => None,
             x => Some(x.to_owned()),
         },
-    ) {
-        return Redirect::to(format!("/instance?id={}", id));
+        overrides: BTreeMap::new(),
+    });
+    validate(&config, &spec)?;
+
+    let started_by = client_id(None, access.0.as_ref(), rate_limit.client_ip);
+    let outcome = server_manager.start_or_enqueue(spec.clone(), &started_by)?;
+    server_manager.audit_start(&actor, &spec, &outcome);
+
+    return match outcome {
+        StartOutcome::Started(id) => {
+            // Lets whoever started the game manage it from the instance page
+            if let Some(owner_token) = server_manager.owner_token(id) {
+                cookies.add_private(Cookie::new(owner_cookie(id), owner_token));
+            }
+            Ok(Redirect::to(format!("/instance?id={}", id)))
+        },
+        StartOutcome::Queued(ticket) => {
+            // Proves who queued the request once it was started, see `instance`
+            cookies.add_private(Cookie::new(TICKET_COOKIE, ticket.ticket.clone()));
+            Ok(Redirect::to(format!("/?ticket={}", ticket.ticket)))
+        },
+        StartOutcome::Unavailable => Err(ApiError::unavailable("no instances available")),
+    };
+}
+
+/// Cancels a request queued from this browser, which is proven by the [`TICKET_COOKIE`] set when it was queued.
+#[post("/queue/<ticket>/cancel")]
+pub fn cancel_ticket(
+    ticket: String,
+    server_manager: State<Arc<ServerManager>>,
+    _rate_limit: RateLimit,
+    mut cookies: Cookies,
+) -> Redirect {
+    let queued_here = cookies.get_private(TICKET_COOKIE).map_or(false, |cookie| cookie.value() == ticket);
+    if queued_here {
+        server_manager.cancel_ticket(&ticket, None);
+        cookies.remove_private(Cookie::named(TICKET_COOKIE));
     }
+
     return Redirect::to("/");
 }
@@ -1,7 +1,17 @@
-use crate::templates::TERA;
-use rgcp_common::{config::Config, models::InstanceID, AppInfo};
-use rocket::{get, request::State, response::content::Html};
+use crate::{server_manager::ServerManager, session::ViewAccess, templates::render};
+use rgcp_common::{
+    config::Config,
+    models::InstanceID,
+    AppInfo,
+};
+use rocket::{
+    get,
+    http::{Cookie, Cookies},
+    request::State,
+    response::content::Html,
+};
 use serde::Serialize;
+use std::sync::Arc;
 
 #[derive(Serialize)]
 struct InstanceContext {
@@ -10,18 +20,47 @@ struct InstanceContext {
     instance_id: InstanceID,
     instance_port: u16,
     server_url: String,
+    owner_token: Option<String>,
 }
 
 #[get("/instance?<id>")]
-pub fn instance(id: InstanceID, config: State<Config>) -> Html<String> {
+pub fn instance(
+    id: InstanceID,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _access: ViewAccess,
+    // After the guards reading the session cookie, they only get an empty jar while `Cookies` is held
+    mut cookies: Cookies,
+) -> Html<String> {
+    // Whoever queued the game becomes its owner once it was started
+    let ticket = cookies.get_private(TICKET_COOKIE).map(|cookie| cookie.value().to_owned());
+    if let Some((started_id, owner_token)) = ticket.and_then(|ticket| server_manager.take_owner_token(&ticket, None)) {
+        cookies.add_private(Cookie::new(owner_cookie(started_id), owner_token));
+        cookies.remove_private(Cookie::named(TICKET_COOKIE));
+    }
+
+    let owner_token = cookies
+        .get_private(&owner_cookie(id))
+        .map(|cookie| cookie.value().to_owned())
+        .filter(|token| server_manager.is_owner(id, token));
+
     let context = InstanceContext {
         title: config.title.get().to_owned(),
         app_version: AppInfo::get().version_string.to_owned(),
         server_url: config.server_url.get().to_owned(),
         instance_id: id,
         instance_port: *config.server_port + id as u16,
+        owner_token: owner_token,
     };
 
-    let html = TERA.render("instance", &tera::Context::from_serialize(context).unwrap()).unwrap();
+    let html = render("instance", context);
     Html(html)
 }
+
+/// Name of the cookie holding the ticket of a start request queued from the web UI.
+pub const TICKET_COOKIE: &'static str = "queue_ticket";
+
+/// Name of the cookie holding the owner token of an instance started from the web UI.
+pub fn owner_cookie(id: InstanceID) -> String {
+    format!("owner_{}", id)
+}
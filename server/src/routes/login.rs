@@ -0,0 +1,101 @@
+use crate::{
+    audit::Actor,
+    error::{caught, ApiError},
+    passwords::verify_password,
+    rate_limit::RateLimit,
+    server_manager::ServerManager,
+    session::SESSION_COOKIE,
+    templates::render,
+};
+use rgcp_common::{config::Config, models::AuditAction, AppInfo};
+use rocket::{
+    catch,
+    get,
+    http::{uri::Uri, Cookie, Cookies, Method, Status},
+    post,
+    request::{Form, FromForm, Request, State},
+    response::{content::Html, Redirect},
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct LoginContext {
+    title: String,
+    app_version: String,
+    next: String,
+    failed: bool,
+}
+
+#[derive(FromForm)]
+pub struct LoginForm {
+    name: String,
+    password: String,
+    next: Option<String>,
+}
+
+#[get("/login?<next>&<failed>")]
+pub fn login_page(next: Option<String>, failed: Option<bool>, config: State<Config>) -> Html<String> {
+    let context = LoginContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        next: safe_redirect(next),
+        failed: failed.unwrap_or(false),
+    };
+
+    let html = render("login", context);
+    Html(html)
+}
+
+#[post("/login", data = "<form>")]
+pub fn login(
+    form: Form<LoginForm>,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _rate_limit: RateLimit,
+    actor: Actor,
+    mut cookies: Cookies,
+) -> Redirect {
+    let LoginForm { name, password, next } = form.into_inner();
+    let next = safe_redirect(next);
+
+    let valid = config.users.iter().any(|user| user.name == name && verify_password(&user.password_hash, &password));
+    let actor = Actor { name: Some(format!("user:{}", name)), ..actor };
+    let error = if valid { None } else { Some("invalid name or password".to_owned()) };
+    server_manager.audit().record(AuditAction::Login, &actor, None, (), error);
+
+    if !valid {
+        return Redirect::to(format!("/login?failed=true&next={}", Uri::percent_encode(&next)));
+    }
+
+    cookies.add_private(Cookie::new(SESSION_COOKIE, name));
+    return Redirect::to(next);
+}
+
+#[post("/logout")]
+pub fn logout(mut cookies: Cookies) -> Redirect {
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    return Redirect::to("/");
+}
+
+/// Sends visitors of pages which require a login to the login page, after logging in they return to where they were.
+/// API clients get a JSON error instead.
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> Result<Redirect, ApiError> {
+    if request.uri().path().starts_with("/api/") {
+        return Err(caught(request, Status::Unauthorized));
+    }
+
+    let next = match request.method() {
+        Method::Get => request.uri().to_string(),
+        _ => "/".to_owned(),
+    };
+
+    return Ok(Redirect::to(format!("/login?next={}", Uri::percent_encode(&next))));
+}
+
+/// Only allows redirects to paths on this server.
+fn safe_redirect(next: Option<String>) -> String {
+    next.filter(|next| next.starts_with('/') && !next.starts_with("//") && !next.contains('\\'))
+        .unwrap_or_else(|| "/".to_owned())
+}
@@ -0,0 +1,97 @@
+use crate::{match_history::MatchQuery, server_manager::ServerManager, session::ViewAccess, templates::render};
+use rgcp_common::{config::Config, models::*, AppInfo};
+use rocket::{get, request::State, response::content::Html};
+use serde::Serialize;
+use std::sync::Arc;
+
+const MATCHES_PER_PAGE: usize = 25;
+const LEADERBOARD_SIZE: usize = 50;
+
+#[derive(Serialize)]
+struct MatchesContext {
+    title: String,
+    app_version: String,
+    matches: Vec<MatchRecord>,
+    map: Option<String>,
+    player: Option<String>,
+    page: usize,
+    pages: usize,
+}
+
+#[derive(Serialize)]
+struct MatchContext {
+    title: String,
+    app_version: String,
+    record: MatchRecord,
+    archived: bool,
+}
+
+#[derive(Serialize)]
+struct LeaderboardContext {
+    title: String,
+    app_version: String,
+    players: Vec<PlayerStats>,
+}
+
+#[get("/matches?<page>&<map>&<player>")]
+pub fn matches(
+    page: Option<usize>,
+    map: Option<String>,
+    player: Option<String>,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _access: ViewAccess,
+) -> Html<String> {
+    let map = map.filter(|map| !map.is_empty());
+    let player = player.filter(|player| !player.is_empty());
+    let page = page.unwrap_or(0);
+    let query = MatchQuery { map: map.clone(), player: player.clone(), ..MatchQuery::default() };
+    let (total, matches) = server_manager.history().query(&query, page, MATCHES_PER_PAGE);
+
+    let context = MatchesContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        matches,
+        map,
+        player,
+        page,
+        pages: (total + MATCHES_PER_PAGE - 1) / MATCHES_PER_PAGE,
+    };
+
+    let html = render("matches", context);
+    Html(html)
+}
+
+#[get("/match?<id>")]
+pub fn match_record(
+    id: String,
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _access: ViewAccess,
+) -> Option<Html<String>> {
+    let context = MatchContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        record: server_manager.history().get(&id)?,
+        archived: server_manager.archive().get_run(&id).is_some(),
+    };
+
+    let html = render("match", context);
+    Some(Html(html))
+}
+
+#[get("/leaderboard")]
+pub fn leaderboard(
+    config: State<Config>,
+    server_manager: State<Arc<ServerManager>>,
+    _access: ViewAccess,
+) -> Html<String> {
+    let context = LeaderboardContext {
+        title: config.title.get().to_owned(),
+        app_version: AppInfo::get().version_string.to_owned(),
+        players: server_manager.history().leaderboard().into_iter().take(LEADERBOARD_SIZE).collect(),
+    };
+
+    let html = render("leaderboard", context);
+    Html(html)
+}
@@ -1,22 +1,85 @@
 pub mod api;
+pub mod api_v3;
+pub mod archive;
 pub mod index;
 pub mod instance;
+pub mod login;
+pub mod matches;
 pub mod static_files;
 
-use rocket::{routes, Route};
+use crate::error;
+use rocket::{catchers, routes, Catcher, Route};
 
 pub fn get() -> Vec<Route> {
     return routes![
         index::get,
         index::start,
+        index::cancel_ticket,
+        login::login_page,
+        login::login,
+        login::logout,
         static_files::static_file,
         api::version,
         api::logs,
         api::get_players,
+        api::instance,
         api::start,
+        api::presets,
+        api::add_preset,
+        api::remove_preset,
+        api::api_keys,
+        api::create_api_key,
+        api::rotate_api_key,
+        api::revoke_api_key,
+        api::schedules,
+        api::add_schedule,
+        api::remove_schedule,
+        api::audit,
+        api::queue,
+        api::queue_ticket,
+        api::queue_ticket_owner_token,
+        api::cancel_queue_ticket,
         api::kill,
+        api::restart,
         api::admin_pw,
         api::events,
+        api::runs,
+        api::run,
+        api::run_file,
+        api::matches,
+        api::match_record,
+        api::player_stats,
+        api::leaderboard,
+        api_v3::version,
+        api_v3::instances,
+        api_v3::start,
+        api_v3::instance,
+        api_v3::kill,
+        api_v3::restart,
+        api_v3::admin_pw,
+        api_v3::logs,
+        api_v3::players,
+        api_v3::events,
+        api_v3::instance_stream,
+        api_v3::event_stream,
+        archive::runs,
+        archive::run,
+        matches::matches,
+        matches::match_record,
+        matches::leaderboard,
         instance::instance
     ];
 }
+
+pub fn catchers() -> Vec<Catcher> {
+    return catchers![
+        login::unauthorized,
+        error::bad_request,
+        error::forbidden,
+        error::not_found,
+        error::unprocessable_entity,
+        error::too_many_requests,
+        error::internal_error,
+        error::unavailable
+    ];
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, TimeZone};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rgcp_common::{config::Config, models::*, utils::now};
+use std::{env::current_dir, str::FromStr, time::Duration};
+
+/// Starts which are overdue by more than this are skipped, e.g. because the panel wasn't running
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Persistent store of scheduled instance starts.
+pub struct Scheduler {
+    db: sled::Db,
+}
+
+impl Scheduler {
+    pub fn new(config: &Config) -> Self {
+        let path = current_dir().unwrap().join(config.schedule_path.get());
+        Scheduler { db: sled::open(path).expect("Cannot open schedules.") }
+    }
+
+    pub fn add(
+        &self,
+        name: String,
+        spec: StartSpec,
+        start_at: Option<Timestamp>,
+        cron: Option<String>,
+        reserve_minutes: u64,
+    ) -> Result<ScheduledStart> {
+        let next_start = match (start_at, &cron) {
+            (Some(start_at), None) if start_at > now() => start_at,
+            (Some(_), None) => bail!("start_at is in the past"),
+            (None, Some(cron)) => next_cron_start(cron, now())?,
+            _ => bail!("exactly one of start_at or cron is required"),
+        };
+
+        let schedule = ScheduledStart {
+            id: thread_rng().sample_iter(&Alphanumeric).take(8).collect(),
+            name: name,
+            spec: spec,
+            start_at: start_at,
+            cron: cron,
+            reserve_minutes: reserve_minutes,
+            next_start: Some(next_start),
+            last_started: None,
+        };
+
+        self.store(&schedule)?;
+        return Ok(schedule);
+    }
+
+    pub fn remove(&self, id: &str) -> Option<ScheduledStart> {
+        let schedule = self.db.remove(id.as_bytes()).ok().flatten().and_then(|s| serde_cbor::from_slice(&s).ok());
+        let _ = self.db.flush();
+        return schedule;
+    }
+
+    /// All schedules, ordered by their next start.
+    pub fn get_all(&self) -> Vec<ScheduledStart> {
+        let mut schedules: Vec<ScheduledStart> = self
+            .db
+            .iter()
+            .values()
+            .filter_map(|schedule| schedule.ok().and_then(|schedule| serde_cbor::from_slice(&schedule).ok()))
+            .collect();
+
+        schedules.sort_by_key(|schedule| schedule.next_start);
+        return schedules;
+    }
+
+    /// Number of instances held back for schedules which are about to start.
+    pub fn reserved(&self, timestamp: Timestamp) -> usize {
+        self.get_all()
+            .iter()
+            .filter_map(|schedule| Some((schedule.next_start?, schedule.reserve_minutes)))
+            .filter(|(next_start, reserve_minutes)| {
+                next_start.saturating_sub(Duration::from_secs(reserve_minutes * 60).as_millis()) <= timestamp
+            })
+            .count()
+    }
+
+    /// Schedules which should be started now, stale ones are skipped.
+    pub fn due(&self, timestamp: Timestamp) -> Vec<ScheduledStart> {
+        let mut due = Vec::new();
+
+        for schedule in self.get_all() {
+            match schedule.next_start {
+                Some(next_start) if next_start + MAX_DELAY.as_millis() < timestamp => {
+                    log::warn!("Skipping scheduled game {}, it is overdue", schedule.name);
+                    self.advance(schedule, None);
+                },
+                Some(next_start) if next_start <= timestamp => due.push(schedule),
+                _ => {},
+            }
+        }
+
+        return due;
+    }
+
+    pub fn mark_started(&self, schedule: ScheduledStart) {
+        self.advance(schedule, Some(now()));
+    }
+
+    /// Moves a schedule on to its next start, one-off schedules are removed.
+    fn advance(&self, mut schedule: ScheduledStart, started: Option<Timestamp>) {
+        schedule.last_started = started.or(schedule.last_started);
+        schedule.next_start = schedule.cron.as_ref().and_then(|cron| next_cron_start(cron, now()).ok());
+
+        let result = match schedule.next_start {
+            Some(_) => self.store(&schedule),
+            None => self.remove(&schedule.id).map(|_| ()).ok_or_else(|| anyhow!("schedule not found")),
+        };
+
+        if let Err(e) = result {
+            log::error!("Couldn't update schedule {}: {}", schedule.name, e);
+        }
+    }
+
+    fn store(&self, schedule: &ScheduledStart) -> Result<()> {
+        self.db.insert(schedule.id.as_bytes(), serde_cbor::to_vec(schedule)?)?;
+        self.db.flush()?;
+        return Ok(());
+    }
+}
+
+fn next_cron_start(cron: &str, after: Timestamp) -> Result<Timestamp> {
+    let schedule = cron::Schedule::from_str(cron).map_err(|e| anyhow!("invalid cron expression: {}", e))?;
+    let after = Local.timestamp_millis_opt(after as i64).single().ok_or_else(|| anyhow!("invalid timestamp"))?;
+    return schedule
+        .after(&after)
+        .next()
+        .map(|next| next.timestamp_millis() as Timestamp)
+        .ok_or_else(|| anyhow!("cron expression has no upcoming start"));
+}
@@ -1,11 +1,31 @@
 use lazy_static::lazy_static;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use regex::{Captures, Regex};
-use rgcp_common::{config::Config, models::*};
-use serde::Serialize;
-use std::{env::current_dir, fs, path::PathBuf, time::SystemTime};
-
-use crate::mods::{get_mod, Mod};
+use regex::{Captures, NoExpand, Regex};
+use rgcp_common::{config::Config, models::*, utils::now};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs,
+    io::BufReader,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    api_keys::{constant_time_eq, ApiKeys},
+    archive::Archive,
+    audit::{Actor, AuditLog},
+    error::ApiError,
+    log_buffer::{read_lines, LogBuffer},
+    match_history::MatchHistory,
+    match_tracker::team_compositions,
+    presets::Presets,
+    rate_limit::RateLimiter,
+    scheduler::Scheduler,
+    start_queue::StartQueue,
+    mods::{get_mod, Mod},
+};
 
 trait DBKey {
     fn db_key(&self, prefix: &str) -> Vec<u8>;
@@ -17,11 +37,6 @@ impl DBKey for InstanceID {
     }
 }
 
-fn now() -> u128 {
-    let duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-    return (duration.as_secs() as u128) * 1000 + duration.subsec_millis() as u128;
-}
-
 #[cfg(target_os = "windows")]
 struct Handle(winapi::shared::ntdef::HANDLE);
 
@@ -31,11 +46,35 @@ struct Handle();
 unsafe impl Send for Handle {}
 unsafe impl Sync for Handle {}
 
+/// Who started an instance and how, kept so the instance can be restarted with the same owner.
+#[derive(Serialize, Deserialize)]
+struct InstanceOwner {
+    token: String,
+    spec: StartSpec,
+    /// Client which started the instance, for the start quotas
+    started_by: Option<String>,
+}
+
+pub enum StartOutcome {
+    Started(InstanceID),
+    Queued(QueueTicket),
+    Unavailable,
+}
+
 #[cfg_attr(not(windows), allow(dead_code))]
 pub struct ServerManager {
     config: Config,
     job_handle: Handle,
     db: sled::Db,
+    archive: Archive,
+    history: MatchHistory,
+    queue: Mutex<StartQueue>,
+    scheduler: Scheduler,
+    presets: Presets,
+    api_keys: ApiKeys,
+    rate_limiter: RateLimiter,
+    audit: AuditLog,
+    logs: Arc<Vec<Mutex<LogBuffer>>>,
 }
 
 #[cfg_attr(not(windows), allow(dead_code))]
@@ -59,13 +98,28 @@ impl ServerManager {
             return None;
         }
 
+        let logs = (0..*config.max_instances)
+            .map(|_| Mutex::new(LogBuffer::new(*config.live_log_max_lines, *config.live_log_max_bytes)))
+            .collect();
+
         let server_manager = ServerManager {
+            archive: Archive::new(&config),
+            history: MatchHistory::new(&config),
+            queue: Mutex::new(StartQueue::new(*config.start_queue_size)),
+            scheduler: Scheduler::new(&config),
+            presets: Presets::new(&config),
+            api_keys: ApiKeys::new(&config),
+            rate_limiter: RateLimiter::new(config.rate_limits.get().clone()),
+            audit: AuditLog::new(&config),
+            logs: Arc::new(logs),
             config: config,
             job_handle: ServerManager::init_job_handle(),
-            db: sled::Config::new().cache_capacity(10_000_000_000).temporary(true).open().unwrap(),
+            db: sled::Config::new().cache_capacity(64_000_000).temporary(true).open().unwrap(),
         };
 
         server_manager.unlock_all_instances();
+        server_manager.archive.enforce_retention();
+        server_manager.audit.record_changes(&server_manager.config);
 
         if !server_manager.binary_path("RxGame-Win64-Test.exe").exists() {
             println!("RxGame-Win64-Test.exe not found, please make sure \"gigantic_path\" is set in your config.json.");
@@ -80,40 +134,213 @@ impl ServerManager {
         (0..max_instances).filter(|x| self.is_locked(*x)).count()
     }
 
-    pub fn start_new_instance(
-        &self,
-        map: &str,
-        creatures: &[String],
-        max_players: usize,
-        mod_id: &Option<String>,
-    ) -> Option<InstanceID> {
+    pub fn archive(&self) -> &Archive {
+        &self.archive
+    }
+
+    pub fn history(&self) -> &MatchHistory {
+        &self.history
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn presets(&self) -> &Presets {
+        &self.presets
+    }
+
+    pub fn api_keys(&self) -> &ApiKeys {
+        &self.api_keys
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Records a start request and what came of it in the audit log.
+    pub fn audit_start(&self, actor: &Actor, spec: &StartSpec, outcome: &StartOutcome) {
+        let (instance_id, queued, error) = match outcome {
+            StartOutcome::Started(id) => (Some(*id), false, None),
+            StartOutcome::Queued(_) => (None, true, None),
+            StartOutcome::Unavailable => (None, false, Some("no instances available".to_owned())),
+        };
+
+        let params = serde_json::json!({ "spec": spec, "queued": queued });
+        self.audit.record(AuditAction::Start, actor, instance_id, params, error);
+    }
+
+    pub fn start_new_instance(&self, spec: &StartSpec, started_by: Option<String>) -> Option<InstanceID> {
         let id = self.try_get_instance()?;
+        let owner_token = thread_rng().sample_iter(&Alphanumeric).take(32).collect::<String>();
+        self.launch_instance(id, InstanceOwner { token: owner_token, spec: spec.clone(), started_by });
+        return Some(id);
+    }
+
+    fn launch_instance(&self, id: InstanceID, owner: InstanceOwner) {
+        let spec = &owner.spec;
         let port = *self.config.server_port + id as u16;
-        let game_mod = mod_id.clone().and_then(|name| get_mod(&name).map_err(|e| log::error!("{}", e)).ok());
+        let game_mod = spec.game_mod.clone().and_then(|name| get_mod(&name).map_err(|e| log::error!("{}", e)).ok());
+        let (map, creatures, max_players) = (&spec.map, &spec.creatures, spec.max_players);
 
-        self.generate_game_config_file(id, &game_mod, creatures, max_players);
+        self.generate_game_config_file(id, &game_mod, creatures, max_players, &spec.overrides);
         self.generate_engine_config_file(id, &game_mod);
         self.generate_input_config_file(id, &game_mod);
 
-        let map_override = game_mod.and_then(|m| m.meta.map).unwrap_or_else(|| map.to_owned());
-        self.run_instance(id, &map_override, port);
+        let map_override = game_mod.clone().and_then(|m| m.meta.map).unwrap_or_else(|| map.to_owned());
+        let started_at = now();
+        let run = RunInfo {
+            run_id: format!("{}-{}", started_at, id),
+            instance_id: id,
+            map: map_override.clone(),
+            creatures: game_mod.clone().and_then(|m| m.meta.creatures).unwrap_or_else(|| creatures.to_vec()),
+            max_players: game_mod.clone().and_then(|m| m.meta.number_of_players).unwrap_or(max_players),
+            game_mod: game_mod.map(|m| m.id),
+            started_at: started_at,
+            finished_at: None,
+            exit_code: None,
+        };
+
+        let owners = self.db.open_tree("owners").unwrap();
+        let _ = owners.insert(id.to_be_bytes(), serde_cbor::to_vec(&owner).unwrap());
 
-        return Some(id);
+        self.run_instance(id, &map_override, port, run);
     }
 
-    pub fn kill_instance(&self, id: InstanceID) -> Option<String> {
+    /// Starts a new instance right away if one is free, otherwise queues the request.
+    ///
+    /// Fails if `started_by` has used up its start quotas.
+    pub fn start_or_enqueue(&self, spec: StartSpec, started_by: &str) -> Result<StartOutcome, ApiError> {
+        let mut queue = self.queue.lock().unwrap();
+        self.rate_limiter.check_start(started_by, self.instances_of(started_by) + queue.waiting_for(started_by))?;
+
+        // Don't let new requests jump the queue or take instances reserved for scheduled games
+        if !queue.has_waiting() && self.has_unreserved_instance() {
+            if let Some(id) = self.start_new_instance(&spec, Some(started_by.to_owned())) {
+                self.rate_limiter.record_start(started_by);
+                return Ok(StartOutcome::Started(id));
+            }
+        }
+
+        return Ok(match queue.enqueue(spec, Some(started_by.to_owned())) {
+            Some(ticket) => {
+                self.rate_limiter.record_start(started_by);
+                StartOutcome::Queued(ticket)
+            },
+            None => StartOutcome::Unavailable,
+        });
+    }
+
+    /// Number of running instances started by a client.
+    fn instances_of(&self, started_by: &str) -> usize {
+        let max_instances = *self.config.max_instances.get();
+        (0..max_instances)
+            .filter_map(|id| self.get_owner(id))
+            .filter(|owner| owner.started_by.as_deref() == Some(started_by))
+            .count()
+    }
+
+    pub fn get_ticket(&self, ticket: &str) -> Option<QueueTicket> {
+        self.queue.lock().unwrap().get(ticket)
+    }
+
+    /// Hands the owner token of a started ticket over to whoever queued it, `started_by` must match the client which
+    /// queued it unless the ticket was already proven otherwise.
+    pub fn take_owner_token(&self, ticket: &str, started_by: Option<&str>) -> Option<(InstanceID, String)> {
+        let mut queue = self.queue.lock().unwrap();
+        if started_by.is_some() && queue.started_by(ticket).as_deref() != started_by {
+            return None;
+        }
+
+        let (id, owner_token) = queue.take_owner_token(ticket)?;
+        return Some((id, owner_token)).filter(|(id, owner_token)| self.is_owner(*id, owner_token));
+    }
+
+    /// Cancels a waiting ticket, with `started_by` only if it was queued by that client.
+    pub fn cancel_ticket(&self, ticket: &str, started_by: Option<&str>) -> Option<QueueTicket> {
+        let mut queue = self.queue.lock().unwrap();
+        if started_by.is_some() && queue.started_by(ticket).as_deref() != started_by {
+            return None;
+        }
+
+        return queue.cancel(ticket);
+    }
+
+    pub fn get_queue(&self) -> Vec<QueueTicket> {
+        self.queue.lock().unwrap().waiting()
+    }
+
+    /// Launches scheduled games when they are due and queued start requests as soon as instances become available.
+    pub fn start_worker(server_manager: &Arc<Self>) {
+        let server_manager = Arc::downgrade(server_manager);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+
+            match server_manager.upgrade() {
+                Some(server_manager) => {
+                    server_manager.process_restarts();
+                    server_manager.process_schedules();
+                    server_manager.process_queue();
+                },
+                None => return,
+            }
+        });
+    }
+
+    pub fn open_url(&self, id: InstanceID) -> String {
+        format!("{}:{}", *self.config.server_url, *self.config.server_port + id as u16)
+    }
+
+    pub fn kill_instance(&self, id: InstanceID) -> Result<(), ApiError> {
         if !self.is_locked(id) {
-            return Some(format!("Invalid instance"));
+            return Err(ApiError::not_found(format!("instance {} isn't running", id)));
         }
 
         if let Ok(db) = self.db.open_tree("kill") {
-            return match db.insert(id.to_be_bytes(), &[]) {
-                Ok(_) => None,
-                _ => Some(format!("Couldn't kill server")),
-            };
+            if db.insert(id.to_be_bytes(), &[]).is_ok() {
+                return Ok(());
+            }
+        }
+
+        return Err(ApiError::internal("couldn't kill server"));
+    }
+
+    /// Kills an instance and starts it again with the same settings and owner once it has shut down.
+    pub fn restart_instance(&self, id: InstanceID) -> Result<(), ApiError> {
+        let owner = match self.get_owner(id) {
+            Some(owner) => owner,
+            None => return Err(ApiError::not_found(format!("instance {} isn't running", id))),
+        };
+
+        let restarts = self.db.open_tree("restarts").unwrap();
+        if restarts.contains_key(id.to_be_bytes()).unwrap_or(false) {
+            return Err(ApiError::conflict(format!("instance {} is already restarting", id)));
+        }
+        if restarts.insert(id.to_be_bytes(), serde_cbor::to_vec(&owner).unwrap()).is_err() {
+            return Err(ApiError::internal("couldn't restart server"));
+        }
+
+        let result = self.kill_instance(id);
+        if result.is_err() {
+            let _ = restarts.remove(id.to_be_bytes());
         }
 
-        return Some(format!("Couldn't kill server"));
+        return result;
+    }
+
+    /// Token granting kill, restart and admin password access to a single instance.
+    pub fn owner_token(&self, id: InstanceID) -> Option<String> {
+        self.get_owner(id).map(|owner| owner.token)
+    }
+
+    pub fn is_owner(&self, id: InstanceID, token: &str) -> bool {
+        self.owner_token(id)
+            .map(|owner_token| constant_time_eq(owner_token.as_bytes(), token.as_bytes()))
+            .unwrap_or(false)
     }
 
     pub fn get_admin_pw(&self, id: InstanceID) -> Option<String> {
@@ -131,13 +358,24 @@ impl ServerManager {
     }
 
     pub fn get_logs(&self, id: InstanceID, from_line: u64, to_line: u64) -> Vec<String> {
-        return self
-            .db
-            .open_tree(id.db_key("logs"))
-            .unwrap()
-            .range(from_line.to_be_bytes()..to_line.to_be_bytes())
-            .map(|l| String::from_utf8_lossy(&l.unwrap().1).into_owned())
-            .collect();
+        let (first_line, buffered) = match self.logs.get(id) {
+            Some(buffer) => {
+                let buffer = buffer.lock().unwrap();
+                (buffer.first_line(), buffer.get(from_line, to_line))
+            },
+            None => return Vec::new(),
+        };
+
+        if from_line >= first_line {
+            return buffered;
+        }
+
+        // Older lines are no longer kept in memory, read them from the log file instead
+        let mut lines = fs::File::open(Self::log_path(id))
+            .map(|file| read_lines(BufReader::new(file), from_line, to_line.min(first_line)))
+            .unwrap_or_default();
+        lines.extend(buffered);
+        return lines;
     }
 
     pub fn get_players(&self, id: InstanceID) -> Vec<Player> {
@@ -150,16 +388,44 @@ impl ServerManager {
             .collect();
     }
 
+    pub fn get_instance(&self, id: InstanceID) -> Option<InstanceResponse> {
+        if !self.is_locked(id) {
+            return None;
+        }
+
+        let players = self.get_players(id);
+        let read = |tree: &str| -> Option<sled::IVec> { self.db.open_tree(tree).ok()?.get(id.to_be_bytes()).ok()? };
+
+        return Some(InstanceResponse {
+            instance_id: id,
+            run: read("runs").and_then(|run| serde_cbor::from_slice(&run).ok()),
+            score: read("scores").and_then(|score| serde_cbor::from_slice(&score).ok()).unwrap_or_default(),
+            teams: team_compositions(&players),
+            players: players,
+        });
+    }
+
+    /// Running instances.
+    pub fn get_instances(&self) -> Vec<InstanceResponse> {
+        let max_instances = *self.config.max_instances.get();
+        return (0..max_instances).filter_map(|id| self.get_instance(id)).collect();
+    }
+
     pub fn get_events(&self, last_timestamp: u128) -> Vec<Event> {
         let max_instances = *self.config.max_instances.get();
-        return (0..max_instances)
-            .filter_map(|id| self.db.open_tree(id.db_key("events")).ok())
-            .flat_map(|tree| {
-                tree.range((last_timestamp + 1).to_be_bytes()..)
-                    .map(|b| serde_cbor::from_slice(&b.unwrap().1).unwrap())
-                    .collect::<Vec<Event>>()
-            })
-            .collect();
+        return (0..max_instances).flat_map(|id| self.get_instance_events(id, last_timestamp)).collect();
+    }
+
+    /// Events of a single instance after `last_timestamp`.
+    pub fn get_instance_events(&self, id: InstanceID, last_timestamp: u128) -> Vec<Event> {
+        return match self.db.open_tree(id.db_key("events")) {
+            Ok(tree) => tree
+                .range((last_timestamp + 1).to_be_bytes()..)
+                .values()
+                .filter_map(|event| event.ok().and_then(|event| serde_cbor::from_slice(&event).ok()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
     }
 
     #[cfg(target_os = "windows")]
@@ -235,11 +501,17 @@ impl ServerManager {
             .join(format!("gcp_{}.log", id))
     }
 
-    fn create_event(db: &sled::Tree, id: &str, instance_id: InstanceID, description: String) {
-        Self::create_event_ex(db, id, instance_id, description, &{});
+    fn create_event(batch: &mut sled::Batch, id: &str, instance_id: InstanceID, description: String) {
+        Self::create_event_ex(batch, id, instance_id, description, &{});
     }
 
-    fn create_event_ex(db: &sled::Tree, id: &str, instance_id: InstanceID, description: String, data: &impl Serialize) {
+    fn create_event_ex(
+        batch: &mut sled::Batch,
+        id: &str,
+        instance_id: InstanceID,
+        description: String,
+        data: &impl Serialize,
+    ) {
         let timestamp = now();
         let event = Event {
             id: id.to_owned(),
@@ -249,20 +521,120 @@ impl ServerManager {
             timestamp: timestamp,
         };
 
-        db.insert(timestamp.to_be_bytes(), serde_cbor::to_vec(&event).unwrap()).unwrap();
+        batch.insert(&timestamp.to_be_bytes(), serde_cbor::to_vec(&event).unwrap());
+    }
+
+    fn process_restarts(&self) {
+        let restarts = self.db.open_tree("restarts").unwrap();
+        for (key, owner) in restarts.iter().filter_map(Result::ok) {
+            let owner: InstanceOwner = match serde_cbor::from_slice(&owner) {
+                Ok(owner) => owner,
+                Err(_) => {
+                    let _ = restarts.remove(key);
+                    continue;
+                },
+            };
+
+            let mut id_bytes = [0; std::mem::size_of::<InstanceID>()];
+            id_bytes.copy_from_slice(&key);
+            let id = InstanceID::from_be_bytes(id_bytes);
+
+            // Wait for the old game to shut down, prefer keeping the instance unless someone else took it meanwhile
+            let new_id = if self.try_lock_instance(id) {
+                Some(id)
+            }
+            else if self.owner_token(id).as_ref() != Some(&owner.token) {
+                self.try_get_instance()
+            }
+            else {
+                continue;
+            };
+
+            if let Some(new_id) = new_id {
+                println!("Restarting instance I{} on I{}", id, new_id);
+                let _ = restarts.remove(key);
+                self.launch_instance(new_id, owner);
+            }
+        }
+    }
+
+    fn get_owner(&self, id: InstanceID) -> Option<InstanceOwner> {
+        if !self.is_locked(id) {
+            return None;
+        }
+
+        let owners = self.db.open_tree("owners").ok()?;
+        return owners.get(id.to_be_bytes()).ok().flatten().and_then(|owner| serde_cbor::from_slice(&owner).ok());
+    }
+
+    fn process_schedules(&self) {
+        for schedule in self.scheduler.due(now()) {
+            if self.find_free_instance().is_none() {
+                break;
+            }
+
+            match self.start_new_instance(&schedule.spec, None) {
+                Some(id) => {
+                    println!("Starting scheduled game {} on instance I{}", schedule.name, id);
+                    let actor = Actor { client_ip: None, name: Some(format!("schedule:{}", schedule.name)) };
+                    self.audit_start(&actor, &schedule.spec, &StartOutcome::Started(id));
+                    self.scheduler.mark_started(schedule);
+                },
+                None => break,
+            }
+        }
+    }
+
+    fn process_queue(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.prune();
+
+        while let Some(ticket) = queue.next() {
+            if !self.has_unreserved_instance() {
+                break;
+            }
+
+            let started_by = queue.started_by(&ticket.ticket);
+            match self.start_new_instance(&ticket.spec, started_by.clone()) {
+                Some(id) => {
+                    println!("Starting queued request {} on instance I{}", ticket.ticket, id);
+                    let actor = Actor { client_ip: None, name: started_by };
+                    self.audit_start(&actor, &ticket.spec, &StartOutcome::Started(id));
+                    queue.mark_started(&ticket.ticket, id, self.open_url(id), self.owner_token(id));
+                },
+                None => break,
+            }
+        }
+    }
+
+    /// Whether `id` is one of the configured instances, running or not.
+    pub fn is_instance(&self, id: InstanceID) -> bool {
+        id < *self.config.max_instances.get()
     }
 
     fn is_locked(&self, id: InstanceID) -> bool {
         self.instance_path(id).exists()
     }
 
+    fn has_unreserved_instance(&self) -> bool {
+        let free_instances = *self.config.max_instances.get() - self.running_instances();
+        return free_instances > self.scheduler.reserved(now());
+    }
+
     fn find_free_instance(&self) -> Option<InstanceID> {
         let max_instances = *self.config.max_instances.get();
         (0..max_instances).find(|x| !self.is_locked(*x))
     }
 
     fn try_get_instance(&self) -> Option<InstanceID> {
-        self.find_free_instance().and_then(|id| fs::create_dir_all(self.instance_path(id)).map(|_| id).ok())
+        let max_instances = *self.config.max_instances.get();
+        (0..max_instances).find(|id| self.try_lock_instance(*id))
+    }
+
+    /// Claims an instance by creating its directory, which fails if the instance is already in use.
+    fn try_lock_instance(&self, id: InstanceID) -> bool {
+        let path = self.instance_path(id);
+        return path.parent().map_or(false, |parent| fs::create_dir_all(parent).is_ok()) && fs::create_dir(path).is_ok();
     }
 
     fn unlock_instance(&self, id: InstanceID) {
@@ -284,6 +656,7 @@ impl ServerManager {
         game_mod: &Option<Mod>,
         creatures: &[String],
         max_players: usize,
+        overrides: &BTreeMap<String, String>,
     ) {
         lazy_static! {
             static ref RE_CREATURE: Regex = Regex::new(r#"DefaultMinionLoadout\[(\d+)]="\w*""#).unwrap();
@@ -338,6 +711,11 @@ impl ServerManager {
             .replace_all(&config, |_: &Captures| format!("AdminPassword={}", admin_pw).to_owned())
             .into_owned();
 
+        for (key, value) in overrides {
+            let re_key = Regex::new(&format!(r#"(?m)^{}=[^\r\n]*"#, regex::escape(key))).unwrap();
+            config = re_key.replace_all(&config, NoExpand(&format!("{}={}", key, value))).into_owned();
+        }
+
         fs::write(self.instance_path(id).join("DefaultGame.ini"), config).unwrap();
     }
 
@@ -353,7 +731,7 @@ impl ServerManager {
         fs::write(self.instance_path(id).join("DefaultInput.ini"), config).unwrap();
     }
 
-    fn run_instance(&self, id: InstanceID, map: &str, port: u16) {
+    fn run_instance(&self, id: InstanceID, map: &str, port: u16, run: RunInfo) {
         if Self::log_path(id).exists() {
             std::fs::remove_file(Self::log_path(id)).unwrap();
         }
@@ -370,52 +748,72 @@ impl ServerManager {
         ));
 
         if let Some(process_handle) = process_handle {
-            self.watch_process(process_handle, id);
+            self.watch_process(process_handle, id, run);
         }
     }
 
     #[cfg(target_os = "windows")]
-    fn watch_process(&self, handle: Handle, id: InstanceID) {
-        use std::io::{BufRead, Seek};
+    fn watch_process(&self, handle: Handle, id: InstanceID, mut run: RunInfo) {
+        use crate::{
+            log_rules::LogParser,
+            log_tail::{LogChange, LogTail},
+            match_tracker::{MatchTracker, MatchUpdate},
+        };
+        use rgcp_common::config::builtin_log_rules;
+        use std::time::Duration;
         use winapi::um::{
             minwinbase::STILL_ACTIVE,
             processthreadsapi::{GetExitCodeProcess, TerminateProcess},
         };
 
-        lazy_static! {
-            static ref RE_PLAYER_JOINED: Regex = Regex::new(r#"DevNet: Join succeeded: (.*?) playerid="#).unwrap();
-            static ref RE_PLAYER_LOCKED: Regex = Regex::new(r#"RxPlayerController::PlayerWaiting:FinishLockingCharacterSelection (.*?) LOCKED HeroProviderIndex:'\d+' RxGameContent\.RxPawn_(.*?)$"#).unwrap();
-            static ref RE_PREROUND_BEGIN: Regex = Regex::new(r#"-------------> PREROUND BeginState <-----------------"#).unwrap();
-            static ref RE_PREROUND_END: Regex = Regex::new(r#"Starting match..."#).unwrap();
-        }
+        let game_mod = run.game_mod.as_ref().and_then(|name| get_mod(name).ok());
+        let builtin_rules = builtin_log_rules();
+        let mod_rules = game_mod.iter().flat_map(|m| m.meta.log_rules.iter().flatten());
+        let rules = builtin_rules.iter().chain(self.config.log_rules.iter()).chain(mod_rules);
+        let parser = LogParser::new(rules, &self.config.heroes);
 
-        let logs = self.db.open_tree(id.db_key("logs")).unwrap();
+        let logs = self.logs.clone();
         let events = self.db.open_tree(id.db_key("events")).unwrap();
         let players = self.db.open_tree(id.db_key("players")).unwrap();
+        let runs = self.db.open_tree("runs").unwrap();
+        let scores = self.db.open_tree("scores").unwrap();
         let kill = self.db.open_tree("kill").unwrap();
         let admin_pws = self.db.open_tree("admin_dbs").unwrap();
+        let owners = self.db.open_tree("owners").unwrap();
         let instance_path = self.instance_path(id);
+        let archive = self.archive.clone();
+        let history = self.history.clone();
+        let poll_interval = Duration::from_millis(*self.config.log_poll_interval_ms);
+        let heroes = self.config.heroes.get().clone();
 
         let _ = kill.remove(id.to_be_bytes());
         let _ = events.clear();
+        let _ = runs.insert(id.to_be_bytes(), serde_cbor::to_vec(&run).unwrap());
+        let _ = scores.remove(id.to_be_bytes());
 
         let port = *self.config.server_port;
         let url = self.config.server_url.get().clone();
         let open_url = if id == 0 && port == 7777 { url } else { format!("{}:{}", url, port) };
+        let mut batch = sled::Batch::default();
         Self::create_event_ex(
-            &events,
+            &mut batch,
             EVENT_SERVER_READY,
             id,
             format!("Server started, please connect using \"open {}\"", &open_url),
             &EvenDataServerReady { open_url: open_url },
         );
+        let _ = events.apply_batch(batch);
 
 
         std::thread::spawn(move || {
+            let instance_dir = instance_path.clone();
             let _cleanup = rgcp_common::utils::DropGuard::new(Some(instance_path), |instance_path| {
-                let _ = logs.clear();
+                logs[id].lock().unwrap().clear();
                 let _ = players.clear();
+                let _ = runs.remove(id.to_be_bytes());
+                let _ = scores.remove(id.to_be_bytes());
                 let _ = admin_pws.remove(id.to_be_bytes());
+                let _ = owners.remove(id.to_be_bytes());
                 let _ = kill.remove(id.to_be_bytes());
 
                 if let Some(instance_path) = instance_path {
@@ -424,113 +822,129 @@ impl ServerManager {
                 }
             });
 
+            let mut tail = LogTail::new(&Self::log_path(id));
             let mut exit_code = STILL_ACTIVE;
+            let mut killed = false;
             let mut last_line: u64 = 0;
-            let mut last_size: u64 = 0;
+            let mut tracker = MatchTracker::new(heroes.clone());
             while exit_code == STILL_ACTIVE {
-                std::thread::sleep(std::time::Duration::from_millis(250));
+                tail.wait(poll_interval);
 
                 if kill.contains_key(id.to_be_bytes()).unwrap_or(false) {
                     unsafe {
                         TerminateProcess(handle.0, 1);
                     }
+                    killed = true;
                 }
 
-                if let Ok(log_file) = std::fs::OpenOptions::new().read(true).open(Self::log_path(id)) {
-                    if let Ok(metadata) = log_file.metadata() {
-                        let size = metadata.len();
+                // Query the exit code before reading, so everything written before the server stopped is still read
+                unsafe { GetExitCodeProcess(handle.0, &mut exit_code) };
 
-                        let mut reader = std::io::BufReader::new(log_file);
+                let mut new_events = sled::Batch::default();
+                let mut new_players = sled::Batch::default();
+                let mut new_score = None;
+                let mut log = logs[id].lock().unwrap();
 
-                        if size < last_size {
-                            logs.clear().unwrap();
+                for change in tail.read() {
+                    let line = match change {
+                        LogChange::Line(line) => line,
+                        LogChange::Reset => {
+                            log.clear();
                             last_line = 0;
-                            last_size = 0;
+                            tracker.reset();
+                            new_events = sled::Batch::default();
+                            new_players = sled::Batch::default();
+                            new_score = None;
+                            // Only the server ready event doesn't come from the log, everything else is parsed again
+                            for (key, event) in events.iter().filter_map(Result::ok) {
+                                let from_log = serde_cbor::from_slice::<Event>(&event)
+                                    .map(|event| event.id != EVENT_SERVER_READY)
+                                    .unwrap_or(true);
+                                if from_log {
+                                    let _ = events.remove(key);
+                                }
+                            }
+                            let _ = players.clear();
+                            let _ = scores.remove(id.to_be_bytes());
+                            continue;
+                        },
+                    };
+
+                    println!("I{} {}: {}", id, last_line as u64, line);
+
+                    for event in parser.parse(&line) {
+                        println!("I{} {}: {}", id, event.id, event.description);
+
+                        match tracker.apply(&event, now()) {
+                            Some(MatchUpdate::Player(player)) => {
+                                new_players.insert(player.name.as_bytes(), serde_cbor::to_vec(player).unwrap());
+                            },
+                            Some(MatchUpdate::Score(score)) => new_score = Some(serde_cbor::to_vec(score).unwrap()),
+                            None => {},
                         }
 
-                        reader.seek(std::io::SeekFrom::Start(last_size)).unwrap();
-
-                        reader.lines().map(|l| l.unwrap()).for_each(|line| {
-                            println!("I{} {}: {}", id, last_line as u64, line);
-
-                            if RE_PLAYER_JOINED.is_match(&line) {
-                                let cap = RE_PLAYER_JOINED.captures(&line).unwrap();
-                                let player = Player { name: cap.get(1).unwrap().as_str().to_owned(), hero: None };
-
-                                println!("Player joined: {}", &player.name);
-
-                                players
-                                    .insert(
-                                        cap.get(1).unwrap().as_str().as_bytes(),
-                                        serde_cbor::to_vec(&player).unwrap(),
-                                    )
-                                    .unwrap();
+                        if event.data.is_empty() {
+                            Self::create_event(&mut new_events, &event.id, id, event.description);
+                        }
+                        else {
+                            Self::create_event_ex(&mut new_events, &event.id, id, event.description, &event.data);
+                        }
+                    }
 
-                                Self::create_event(
-                                    &events,
-                                    EVENT_PLAYER_JOIN,
-                                    id,
-                                    format!("Player {} joined", &player.name),
-                                );
-                            }
+                    log.push(line);
+                    last_line += 1;
+                }
 
-                            if RE_PLAYER_LOCKED.is_match(&line) {
-                                let cap = RE_PLAYER_LOCKED.captures(&line).unwrap();
-                                let player = Player {
-                                    name: cap.get(1).unwrap().as_str().to_owned(),
-                                    hero: Some(cap.get(2).unwrap().as_str().to_owned()),
-                                };
-
-                                println!(
-                                    "Player locked: {} -> {}",
-                                    cap.get(1).unwrap().as_str().to_owned(),
-                                    cap.get(2).unwrap().as_str().to_owned()
-                                );
-
-                                Self::create_event(
-                                    &events,
-                                    EVENT_PLAYER_LOCK,
-                                    id,
-                                    format!(
-                                        "Player {} locked char {}",
-                                        &player.name,
-                                        &cap.get(2).unwrap().as_str().to_owned()
-                                    ),
-                                );
-
-                                players
-                                    .insert(
-                                        cap.get(1).unwrap().as_str().as_bytes(),
-                                        serde_cbor::to_vec(&player).unwrap(),
-                                    )
-                                    .unwrap();
-                            }
+                drop(log);
+                events.apply_batch(new_events).unwrap();
+                players.apply_batch(new_players).unwrap();
+                if let Some(score) = new_score {
+                    scores.insert(id.to_be_bytes(), score).unwrap();
+                }
+            }
 
-                            if RE_PREROUND_END.is_match(&line) {
-                                Self::create_event(&events, EVENT_MATCH_STARTING, id, format!("Match starting"));
-                            }
+            println!("Server I{} finished with code: {}", id, exit_code);
 
+            let finished_at = now();
+            run.finished_at = Some(finished_at);
+            run.exit_code = Some(exit_code);
 
-                            logs.insert((last_line).to_be_bytes(), line.into_bytes()).unwrap();
-                            last_line += 1;
-                        });
+            let result = tracker.result(&run, finished_at);
+            let description = match &result.winning_team {
+                Some(team) => format!("Match finished, team {} won", team),
+                None => format!("Match finished"),
+            };
+            let mut batch = sled::Batch::default();
+            Self::create_event_ex(&mut batch, EVENT_MATCH_FINISHED, id, description, &result);
+            let _ = events.apply_batch(batch);
 
-                        last_size = size;
-                    }
-                }
+            let run_events: Vec<Event> =
+                events.iter().values().filter_map(|e| e.ok().and_then(|e| serde_cbor::from_slice(&e).ok())).collect();
 
-                unsafe { GetExitCodeProcess(handle.0, &mut exit_code) };
+            if let Err(e) = archive.store_run(&run, &instance_dir, &Self::log_path(id), &run_events, &result) {
+                log::error!("Couldn't archive run {}: {}", run.run_id, e);
             }
 
-            println!("Server I{} finished with code: {}", id, exit_code);
+            let exit_reason = match exit_code {
+                _ if killed => ExitReason::Killed,
+                0 => ExitReason::Finished,
+                _ => ExitReason::Crashed,
+            };
+            if let Err(e) = history.record(&MatchRecord { run: run.clone(), result: result, exit_reason: exit_reason }) {
+                log::error!("Couldn't record match {}: {}", run.run_id, e);
+            }
 
+            // Only keep the finished event around, so clients polling for events still get to see it
+            let finished = events.last().ok().flatten();
             let _ = events.clear();
-            Self::create_event(&events, EVENT_MATCH_FINISHED, id, format!("Match finished"));
+            if let Some((key, value)) = finished {
+                let _ = events.insert(key, value);
+            }
         });
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn watch_process(&self, _handle: Handle, _id: InstanceID) {}
+    fn watch_process(&self, _handle: Handle, _id: InstanceID, _run: RunInfo) {}
 
     #[cfg(target_os = "windows")]
     fn start_process(&self, cmd: &str) -> Option<Handle> {
@@ -0,0 +1,59 @@
+use rgcp_common::config::{Config, WebLogin};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Request, State},
+    Outcome::{self, Success},
+};
+
+pub const SESSION_COOKIE: &'static str = "user";
+
+/// A user logged in to the web UI.
+pub struct User(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        if let Success(config) = request.guard::<State<Config>>() {
+            let name = request.cookies().get_private(SESSION_COOKIE).map(|cookie| cookie.value().to_owned());
+
+            // Sessions of users which were removed from the config are no longer valid
+            return match name.filter(|name| config.users.iter().any(|user| user.name == *name)) {
+                Some(name) => Success(User(name)),
+                None => Outcome::Forward(()),
+            };
+        }
+
+        return Outcome::Failure((Status::InternalServerError, ()));
+    }
+}
+
+/// Defines a request guard for web pages which fails with `401 Unauthorized` when nobody is logged in and the
+/// `web_login` policy requires a login for the given level.
+macro_rules! web_access {
+    ($name:ident, $level:expr) => {
+        #[allow(dead_code)]
+        pub struct $name(pub Option<User>);
+
+        impl<'a, 'r> FromRequest<'a, 'r> for $name {
+            type Error = ();
+
+            fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+                let required = match request.guard::<State<Config>>() {
+                    Success(config) => *config.web_login >= $level,
+                    _ => return Outcome::Failure((Status::InternalServerError, ())),
+                };
+
+                return match User::from_request(request) {
+                    Success(user) => Success($name(Some(user))),
+                    Outcome::Forward(_) if required => Outcome::Failure((Status::Unauthorized, ())),
+                    Outcome::Forward(_) => Success($name(None)),
+                    Outcome::Failure(failure) => Outcome::Failure(failure),
+                };
+            }
+        }
+    };
+}
+
+web_access!(ViewAccess, WebLogin::All);
+web_access!(StartAccess, WebLogin::Start);
@@ -0,0 +1,134 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rgcp_common::{models::*, utils::now};
+use std::{collections::BTreeMap, time::Duration};
+
+/// How long started or cancelled tickets can still be polled
+const TICKET_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// FIFO queue of start requests waiting for a free instance.
+pub struct StartQueue {
+    max_len: usize,
+    tickets: Vec<QueueTicket>,
+    /// Client which queued each ticket, kept out of [`QueueTicket`] so it isn't exposed
+    started_by: BTreeMap<String, String>,
+    /// Owner tokens of started tickets until they were handed over to whoever queued them
+    owner_tokens: BTreeMap<String, String>,
+}
+
+impl StartQueue {
+    /// A queue with `max_len` 0 is disabled and won't accept any requests.
+    pub fn new(max_len: usize) -> Self {
+        StartQueue { max_len, tickets: Vec::new(), started_by: BTreeMap::new(), owner_tokens: BTreeMap::new() }
+    }
+
+    pub fn has_waiting(&self) -> bool {
+        self.tickets.iter().any(|ticket| ticket.state == TicketState::Waiting)
+    }
+
+    /// Queues a start request, returns `None` if the queue is full or disabled.
+    pub fn enqueue(&mut self, spec: StartSpec, started_by: Option<String>) -> Option<QueueTicket> {
+        if self.waiting().len() >= self.max_len {
+            return None;
+        }
+
+        let ticket: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+        if let Some(started_by) = started_by {
+            self.started_by.insert(ticket.clone(), started_by);
+        }
+
+        self.tickets.push(QueueTicket {
+            ticket: ticket,
+            state: TicketState::Waiting,
+            position: None,
+            instance_id: None,
+            open_url: None,
+            spec: spec,
+            created_at: now(),
+        });
+
+        return self.tickets.last().map(|ticket| self.with_position(ticket));
+    }
+
+    pub fn get(&self, ticket: &str) -> Option<QueueTicket> {
+        self.tickets.iter().find(|t| t.ticket == ticket).map(|ticket| self.with_position(ticket))
+    }
+
+    pub fn started_by(&self, ticket: &str) -> Option<String> {
+        self.started_by.get(ticket).cloned()
+    }
+
+    /// Number of waiting tickets queued by a client.
+    pub fn waiting_for(&self, started_by: &str) -> usize {
+        self.waiting().iter().filter(|ticket| self.started_by(&ticket.ticket).as_deref() == Some(started_by)).count()
+    }
+
+    /// Cancels a waiting ticket, tickets which were already started are left untouched.
+    pub fn cancel(&mut self, ticket: &str) -> Option<QueueTicket> {
+        let ticket = self.tickets.iter_mut().find(|t| t.ticket == ticket)?;
+        if ticket.state == TicketState::Waiting {
+            ticket.state = TicketState::Cancelled;
+        }
+
+        return Some(ticket.clone());
+    }
+
+    /// Waiting tickets in queue order.
+    pub fn waiting(&self) -> Vec<QueueTicket> {
+        self.tickets
+            .iter()
+            .filter(|ticket| ticket.state == TicketState::Waiting)
+            .map(|ticket| self.with_position(ticket))
+            .collect()
+    }
+
+    pub fn next(&self) -> Option<QueueTicket> {
+        self.waiting().into_iter().next()
+    }
+
+    pub fn mark_started(
+        &mut self,
+        ticket: &str,
+        instance_id: InstanceID,
+        open_url: String,
+        owner_token: Option<String>,
+    ) {
+        if let Some(ticket) = self.tickets.iter_mut().find(|t| t.ticket == ticket) {
+            ticket.state = TicketState::Started;
+            ticket.instance_id = Some(instance_id);
+            ticket.open_url = Some(open_url);
+            if let Some(owner_token) = owner_token {
+                self.owner_tokens.insert(ticket.ticket.clone(), owner_token);
+            }
+        }
+    }
+
+    /// Instance and owner token of a started ticket, the token is only handed out once.
+    pub fn take_owner_token(&mut self, ticket: &str) -> Option<(InstanceID, String)> {
+        let instance_id = self.tickets.iter().find(|t| t.ticket == ticket)?.instance_id?;
+        return self.owner_tokens.remove(ticket).map(|owner_token| (instance_id, owner_token));
+    }
+
+    /// Forgets tickets that are no longer waiting and older than [`TICKET_RETENTION`].
+    pub fn prune(&mut self) {
+        let cutoff = now().saturating_sub(TICKET_RETENTION.as_millis());
+        self.tickets.retain(|ticket| ticket.state == TicketState::Waiting || ticket.created_at > cutoff);
+
+        let tickets = &self.tickets;
+        self.started_by.retain(|ticket, _| tickets.iter().any(|t| t.ticket == *ticket));
+        self.owner_tokens.retain(|ticket, _| tickets.iter().any(|t| t.ticket == *ticket));
+    }
+
+    fn with_position(&self, ticket: &QueueTicket) -> QueueTicket {
+        let position = match ticket.state {
+            TicketState::Waiting => self
+                .tickets
+                .iter()
+                .filter(|t| t.state == TicketState::Waiting)
+                .position(|t| t.ticket == ticket.ticket)
+                .map(|position| position + 1),
+            _ => None,
+        };
+
+        QueueTicket { position, ..ticket.clone() }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::assets::Assets;
 use lazy_static::lazy_static;
+use serde::Serialize;
 use tera::Tera;
 
 lazy_static! {
@@ -23,3 +24,10 @@ lazy_static! {
         tera
     };
 }
+
+/// Renders a template, the context goes through JSON text since `Context::from_serialize` fails on `u128` timestamps.
+pub fn render(name: &str, context: impl Serialize) -> String {
+    let json = serde_json::to_string(&context).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    return TERA.render(name, &tera::Context::from_serialize(value).unwrap()).unwrap();
+}
@@ -3,11 +3,69 @@
 mod app_info;
 
 use futures::executor::block_on;
+use rgcp_common::config::{ApiKeyConfig, ApiScope};
 
 fn main() {
     app_info::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("generate-key") {
+        return generate_key(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("hash-password") {
+        return hash_password(&args[1..]);
+    }
+
     let config = rgcp_common::config::Config::load();
 
     block_on(rgcp_server::run(config));
 }
+
+/// `gcp generate-key <name> [scope...]`: prints a new API key together with the config entry holding its hash.
+fn generate_key(args: &[String]) {
+    let name = match args.first() {
+        Some(name) => name.clone(),
+        None => {
+            eprintln!("Usage: gcp generate-key <name> [scope...]");
+            std::process::exit(1);
+        },
+    };
+
+    let scopes: Vec<ApiScope> =
+        match args[1..].iter().map(|scope| serde_json::from_value(scope.clone().into())).collect() {
+            Ok(scopes) if args.len() > 1 => scopes,
+            Ok(_) => vec![ApiScope::Read],
+            Err(e) => {
+                eprintln!("Invalid scope: {}", e);
+                std::process::exit(1);
+            },
+        };
+
+    let key = rgcp_server::generate_key();
+    let entry = ApiKeyConfig { name, key: None, hash: Some(rgcp_server::hash_key(&key)), scopes };
+
+    println!("API key: {}", key);
+    println!("This key is only shown once, add the following entry to \"api_keys\" in your config.json:");
+    println!("{}", serde_json::to_string_pretty(&entry).unwrap());
+}
+
+/// `gcp hash-password`: reads a password from stdin without echoing it and prints the hash to use as
+/// `password_hash` of a web UI user.
+fn hash_password(args: &[String]) {
+    if !args.is_empty() {
+        eprintln!("Usage: gcp hash-password, the password is read from stdin");
+        std::process::exit(1);
+    }
+
+    match rpassword::prompt_password_stderr("Password: ") {
+        Ok(password) if !password.is_empty() => println!("{}", rgcp_server::hash_password(&password)),
+        Ok(_) => {
+            eprintln!("The password must not be empty");
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("Cannot read the password: {}", e);
+            std::process::exit(1);
+        },
+    }
+}